@@ -1,52 +1,149 @@
 extern crate backend;
+extern crate rand;
 
-use std::cmp::Ordering;
-use std::iter::repeat;
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
 use backend::*;
+use rand::{thread_rng, Rng, SeedableRng, StdRng};
+
+/// How many rounds of evolution run between checkpoint saves.
+const CHECKPOINT_INTERVAL: usize = 10;
+
+const USAGE: &'static str = "usage: evolve [--output PATH] [--ticks N] [--seed N]
+              [--checkpoint PATH] [--resume] [--max-genes N]
+              [--sharing RADIUS] [--time-budget SECS]
+    --output PATH       where to write the evolved genome (default: evolved.json)
+    --ticks N           how many ticks each trial may run for (default: 1000)
+    --seed N            seed for the mutation RNG (default: random)
+    --checkpoint PATH   where to save progress every 10 rounds, stopping if anything else
+                        writes there in the meantime (default: checkpoint.json)
+    --resume            continue from the genome in the checkpoint file
+    --max-genes N       stop genomes growing past N genes (default: unlimited)
+    --sharing RADIUS    penalise genomes within RADIUS of each other (default: off)
+    --time-budget SECS  stop after SECS seconds with the best genome so far (default: none)";
+
+/// What the command line asked for.
+struct Options {
+    output: String,
+    cap: u32,
+    seed: Option<usize>,
+    checkpoint: String,
+    resume: bool,
+    config: EvolveConfig,
+}
 
 fn main() {
+    let Options { output, cap, seed, checkpoint, resume, config } =
+        parse_args(env::args().skip(1)).unwrap_or_else(|e| usage(&e));
+    let seed = seed.unwrap_or_else(|| thread_rng().gen());
+    println!("Evolving with seed {}.", seed);
+    let mut rng: StdRng = SeedableRng::from_seed(&[seed][..]);
     let target = 0;
-    let mut fit = Fitness(std::u32::MAX, Genome::new());
-    while fit.0 != 0 {
-        fit = evolve(fit.1, 1000, 1000, |ticks: u32, genome| {
+    let base = if resume {
+        or_exit(Genome::load(&checkpoint), "Failed to load the checkpoint")
+    } else {
+        Genome::new()
+    };
+    let mut checkpointed = if resume {
+        or_exit(fs::metadata(&checkpoint).and_then(|meta| meta.modified()),
+                "Failed to read when the checkpoint was written")
+    } else {
+        SystemTime::now()
+    };
+    let mut fit = Fitness(std::u32::MAX, base);
+    let mut round = 0;
+    let start = Instant::now();
+    while fit.0 != 0 && config.time_budget.map_or(true, |budget| start.elapsed() < budget) {
+        fit = evolve(fit.1, 1000, cap, &config, &mut rng, |ticks: u32, genome| {
             Fitness((target as i64 - ticks as i64).abs() as u32, genome)
         });
+        round += 1;
+        if round % CHECKPOINT_INTERVAL == 0 {
+            checkpointed = or_exit(fit.1.save_unless_modified(&checkpoint, checkpointed),
+                                   "Failed to save the checkpoint");
+        }
     }
-    fit.1.save("evolved.json").unwrap();
+    or_exit(fit.1.save(&output), "Failed to save the evolved genome");
 }
 
-fn evolve<F>(base: Genome, trials: usize, cap: u32, fitness: F) -> Fitness
-where F: Fn(u32, Genome) -> Fitness {
-    repeat(base).take(trials).map(|genome| {
-        let genome = genome.mutate().mutate().mutate();
-        let mut creature = Creature::new();
-        genome.init(&mut creature);
-        for t in 0 .. cap {
-            genome.step(&mut creature);
-            if creature.age() == Age::Baby { return fitness(t, genome) }
+/// Reads the arguments that follow the program's name, or says what is wrong with them.
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options {
+        output: "evolved.json".to_owned(), cap: 1000, seed: None,
+        checkpoint: "checkpoint.json".to_owned(), resume: false, config: EvolveConfig::new()
+    };
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-o" | "--output" => options.output = try!(parse(&arg, args.next())),
+            "-t" | "--ticks" => options.cap = try!(parse(&arg, args.next())),
+            "-s" | "--seed" => options.seed = Some(try!(parse(&arg, args.next()))),
+            "-c" | "--checkpoint" => options.checkpoint = try!(parse(&arg, args.next())),
+            "-r" | "--resume" => options.resume = true,
+            "-m" | "--max-genes" =>
+                options.config.max_genes = Some(try!(parse(&arg, args.next()))),
+            "--sharing" => options.config.sharing = Some(try!(parse(&arg, args.next()))),
+            "--time-budget" => options.config.time_budget =
+                Some(Duration::from_secs(try!(parse(&arg, args.next())))),
+            _ => return Err(format!("Unknown argument {}.", arg)),
         }
-        fitness(cap, genome)
-    }).min().unwrap()
+    }
+    Ok(options)
 }
 
-struct Fitness(u32, Genome);
+fn usage(problem: &str) -> ! {
+    eprintln!("{}\n{}", problem, USAGE);
+    process::exit(1)
+}
 
-impl PartialEq for Fitness {
-    fn eq(&self, other: &Fitness) -> bool {
-        self.0 == other.0
-    }
+/// Gives what `result` holds, or prints `what` went wrong and why and exits.
+fn or_exit<T>(result: io::Result<T>, what: &str) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}: {}", what, e);
+        process::exit(1)
+    })
 }
 
-impl Eq for Fitness {}
+fn parse<T: FromStr>(flag: &str, arg: Option<String>) -> Result<T, String> {
+    arg.and_then(|arg| arg.parse().ok()).ok_or_else(|| format!("{} needs a valid value.", flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl PartialOrd for Fitness {
-    fn partial_cmp(&self, other: &Fitness) -> Option<Ordering> {
-        self.0.partial_cmp(&other.0)
+    fn args(line: &str) -> Result<Options, String> {
+        parse_args(line.split_whitespace().map(|arg| arg.to_owned()))
+    }
+
+    #[test]
+    fn no_arguments_give_the_defaults() {
+        let options = args("").unwrap();
+        assert_eq!((&options.output[..], options.cap, options.seed), ("evolved.json", 1000, None));
+        assert_eq!((&options.checkpoint[..], options.resume), ("checkpoint.json", false));
+        assert!(options.config.max_genes.is_none() && options.config.sharing.is_none());
+        assert!(options.config.time_budget.is_none());
+    }
+
+    #[test]
+    fn every_flag_is_read() {
+        let options = args("-o out.json --ticks 50 -s 7 --checkpoint cp.json -r -m 12 \
+                            --sharing 0.5 --time-budget 30").unwrap();
+        assert_eq!((&options.output[..], options.cap, options.seed), ("out.json", 50, Some(7)));
+        assert_eq!((&options.checkpoint[..], options.resume), ("cp.json", true));
+        assert_eq!((options.config.max_genes, options.config.sharing), (Some(12), Some(0.5)));
+        assert_eq!(options.config.time_budget, Some(Duration::from_secs(30)));
+        let options = args("--output other.json -t 3 --seed 9").unwrap();
+        assert_eq!((&options.output[..], options.cap, options.seed), ("other.json", 3, Some(9)));
     }
-}
 
-impl Ord for Fitness {
-    fn cmp(&self, other: &Fitness) -> Ordering {
-        self.0.cmp(&other.0)
+    #[test]
+    fn bad_arguments_are_refused() {
+        assert_eq!(args("--ticks").err().unwrap(), "--ticks needs a valid value.");
+        assert_eq!(args("--seed many").err().unwrap(), "--seed needs a valid value.");
+        assert_eq!(args("--verbose").err().unwrap(), "Unknown argument --verbose.");
     }
 }
@@ -0,0 +1,145 @@
+extern crate backend;
+extern crate rustc_serialize;
+
+use std::env;
+use std::io::prelude::*;
+use std::io::{BufReader, Result};
+use std::net::{TcpListener, TcpStream};
+use std::process;
+use backend::*;
+use rustc_serialize::json::encode;
+
+const USAGE: &'static str = "usage: server [--genome PATH] [--address ADDR]
+    --genome PATH     the genome to run (default: evolved.json)
+    --address ADDR    where to listen for frontends (default: 127.0.0.1:7878)
+
+Frontends send one command per line and get one line back for each:
+    step [N]            step the creature N times (default: 1), answered by ok
+    inject ID AMOUNT    add AMOUNT of chemical ID, answered by ok
+    drain ID AMOUNT     remove AMOUNT of chemical ID, answered by ok
+    get                 answered by the concentrations as a JSON object keyed by chemical id
+Anything else is answered by a line starting with error. Connections are served one at a time
+and all of them drive the same creature.";
+
+fn main() {
+    let mut genome = "evolved.json".to_owned();
+    let mut address = "127.0.0.1:7878".to_owned();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-g" | "--genome" => genome = args.next().unwrap_or_else(|| usage()),
+            "-a" | "--address" => address = args.next().unwrap_or_else(|| usage()),
+            _ => usage(),
+        }
+    }
+    let mut organism = Organism::new(or_exit(Genome::load(&genome), "Failed to load the genome"));
+    let listener = or_exit(TcpListener::bind(&address[..]), "Failed to listen for frontends");
+    println!("Serving {} on {}.", genome, address);
+    for stream in listener.incoming() {
+        if let Err(e) = stream.and_then(|stream| serve(stream, &mut organism)) {
+            eprintln!("Connection failed: {}", e);
+        }
+    }
+}
+
+fn serve(stream: TcpStream, organism: &mut Organism) -> Result<()> {
+    let mut writer = try!(stream.try_clone());
+    for line in BufReader::new(stream).lines() {
+        let reply = run(&try!(line), organism);
+        try!(writeln!(writer, "{}", reply));
+    }
+    Ok(())
+}
+
+fn run(command: &str, organism: &mut Organism) -> String {
+    let mut words = command.split_whitespace();
+    match (words.next(), words.next(), words.next(), words.next()) {
+        (Some("step"), None, None, None) => organism.step(),
+        (Some("step"), Some(n), None, None) => match n.parse() {
+            Ok(n) => organism.genome.step_n(&mut organism.creature, n),
+            Err(_) => return "error: the number of steps must be a whole number".to_owned(),
+        },
+        (Some(verb @ "inject"), Some(id), Some(amount), None) |
+        (Some(verb @ "drain"), Some(id), Some(amount), None) => {
+            match (id.parse(), amount.parse()) {
+                (Ok(id), Ok(amount)) if verb == "inject" => organism.creature.inject(id, amount),
+                (Ok(id), Ok(amount)) => organism.creature.drain(id, amount),
+                _ => return "error: chemical ids and amounts must be from 0 to 255".to_owned(),
+            }
+        },
+        (Some("get"), None, None, None) =>
+            return encode(&organism.creature.concentrations()).unwrap_or_else(|_| {
+                "error: failed to encode concentrations".to_owned()
+            }),
+        _ => return format!("error: unknown command: {}", command),
+    }
+    "ok".to_owned()
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1)
+}
+
+/// Gives what `result` holds, or prints `what` went wrong and why and exits.
+fn or_exit<T>(result: Result<T>, what: &str) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}: {}", what, e);
+        process::exit(1)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    /// An organism whose only gene makes 5 of chemical 1 every step.
+    fn organism() -> Organism {
+        Organism::new(Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 1, 0, 5, 0, 0, false, false)),
+        ]))
+    }
+
+    #[test]
+    fn commands_drive_the_organism() {
+        let mut organism = organism();
+        assert_eq!(run("step", &mut organism), "ok");
+        assert_eq!(run("step 3", &mut organism), "ok");
+        assert_eq!(run("get", &mut organism), r#"{"1":20}"#);
+        assert_eq!(run("inject 1 100", &mut organism), "ok");
+        assert_eq!(run("drain 1 30", &mut organism), "ok");
+        assert_eq!(run("get", &mut organism), r#"{"1":90}"#);
+    }
+
+    #[test]
+    fn bad_commands_are_answered_with_errors() {
+        let mut organism = organism();
+        let before = organism.creature.concentrations();
+        for command in &["step many", "inject 1 300", "drain one 2", "get 1", "fly", ""] {
+            let reply = run(command, &mut organism);
+            assert!(reply.starts_with("error: "), "{} was answered by {}.", command, reply);
+        }
+        assert_eq!(organism.creature.concentrations(), before);
+    }
+
+    #[test]
+    fn connections_are_served_a_line_at_a_time() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut organism = organism();
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream, &mut organism).unwrap();
+        });
+        let stream = TcpStream::connect(address).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        writer.write_all(b"step 2\nget\n").unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "ok");
+        assert_eq!(lines.next().unwrap().unwrap(), r#"{"1":10}"#);
+        drop(writer);
+        drop(lines);
+        server.join().unwrap();
+    }
+}
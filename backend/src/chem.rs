@@ -1,31 +1,102 @@
 use std::cell::Cell;
-use std::cmp::min;
-use std::collections::HashMap;
-use creature::{Creature, LocusId, LocusValue};
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, HashMap};
+use context::{StepContext, Steppable};
+use creature::{Age, Creature, LocusId, LocusValue};
 use rand::{Rand, Rng};
+use rand::distributions::{IndependentSample, Normal};
+use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
 
 pub type Id = u8;
 pub type Concentration = u8;
+/// Net changes in concentration, keyed by chemical and kept in id order so that anything built up
+/// from them comes out the same on every run. Concentrations are whole numbers that saturate at 0
+/// and 255 whenever they change, so they never leave that range, and a reaction's changes add up
+/// to at most four times 255, which an `i16` holds with room to spare.
+pub type DeltaMap = BTreeMap<Id, i16>;
 
+/// The chemicals in a creature. Ids are small, so they index straight into a table with a slot
+/// for every possible id instead of being hashed.
+#[derive(Clone)]
 pub struct ChemoBody {
-    chems: HashMap<Id, Chemical>
+    chems: Vec<Option<Chemical>>
 }
 
 impl ChemoBody {
     pub fn new() -> ChemoBody {
-        ChemoBody { chems: HashMap::new() }
+        ChemoBody { chems: vec![None; 256] }
+    }
+
+    fn entry(&mut self, id: Id) -> &mut Chemical {
+        self.chems[id as usize].get_or_insert(Chemical::new(id))
     }
 
     pub fn get(&mut self, id: Id) -> &Chemical {
-        self.chems.entry(id).or_insert(Chemical::new(id))
+        self.entry(id)
     }
 
     pub fn concnt(&mut self, id: Id) -> u8 {
-        self.chems.entry(id).or_insert(Chemical::new(id)).concnt()
+        self.entry(id).concnt()
+    }
+
+    /// Reads a concentration without registering the chemical if it is missing.
+    pub fn peek(&self, id: Id) -> Concentration {
+        self.chems[id as usize].map_or(0, |chem| chem.concnt())
+    }
+
+    pub fn snapshot(&self) -> HashMap<Id, Concentration> {
+        self.chems.iter().filter_map(|chem| chem.map(|c| (c.id, c.concnt()))).collect()
+    }
+
+    pub fn ensure(&mut self, id: Id) {
+        self.entry(id);
+    }
+
+    pub fn zero(&mut self) {
+        for chem in self.chems.iter_mut().filter_map(|chem| chem.as_mut()) {
+            *chem = Chemical::new(chem.id);
+        }
+    }
+
+    /// Snaps every concentration to the nearest of `levels + 1` evenly spaced steps between 0 and
+    /// 255, so `levels` of 10 allows 0, 26, 51, 77 and so on. Concentrations exactly halfway
+    /// between two steps go up, and steps that fall between whole numbers are rounded to the
+    /// nearest one. Zero levels leave the body untouched.
+    pub fn quantize(&mut self, levels: u8) {
+        if levels == 0 { return }
+        let step = 255.0 / levels as f32;
+        for chem in self.chems.iter_mut().filter_map(|chem| chem.as_mut()) {
+            let level = (chem.concnt() as f32 / step + 0.5).floor();
+            *chem = Chemical::with_concentration(chem.id, (level * step).round() as u8);
+        }
+    }
+
+    /// Brings a chemical's concentration down to `most` if it is above it.
+    pub fn cap(&mut self, id: Id, most: Concentration) {
+        let val = self.entry(id);
+        if val.concnt() > most {
+            *val = Chemical::with_concentration(id, most);
+        }
+    }
+
+    /// Scales a chemical's concentration by `keep`, rounding down.
+    pub fn scale(&mut self, id: Id, keep: f32) {
+        let val = self.entry(id);
+        *val = Chemical::with_concentration(id, (val.concnt() as f32 * keep) as u8);
+    }
+
+    /// Nudges every concentration by a random amount from `-amplitude` to `amplitude`, clamped
+    /// into 0-255.
+    pub fn jitter<R: Rng>(&mut self, rng: &mut R, amplitude: u8) {
+        let amplitude = amplitude as i16;
+        for chem in self.chems.iter_mut().filter_map(|chem| chem.as_mut()) {
+            let value = chem.concnt() as i16 + rng.gen_range(-amplitude, amplitude + 1);
+            *chem = Chemical::with_concentration(chem.id, max(0, min(value, 255)) as u8);
+        }
     }
 
     pub fn gain(&mut self, id: Id, amount: Concentration) -> bool {
-        let val = self.chems.entry(id).or_insert(Chemical::new(id));
+        let val = self.entry(id);
         if let Some(new) = val.concnt().checked_add(amount) {
             *val = Chemical::with_concentration(id, new);
             true
@@ -37,8 +108,27 @@ impl ChemoBody {
         }
     }
 
+    /// Adds each delta to its chemical, saturating at 255 and bottoming out at zero.
+    pub fn apply(&mut self, deltas: &DeltaMap) {
+        for (&id, &delta) in deltas.iter() {
+            if delta > 0 {
+                self.gain(id, min(delta, 255) as u8);
+            } else {
+                self.drain(id, min(-delta, 255) as u8);
+            }
+        }
+    }
+
+    /// Removes up to `amount` of a chemical, stopping at zero.
+    pub fn drain(&mut self, id: Id, amount: Concentration) {
+        if !self.lose(id, amount) {
+            let concnt = self.concnt(id);
+            self.lose(id, concnt);
+        }
+    }
+
     pub fn lose(&mut self, id: Id, amount: Concentration) -> bool {
-        let val = self.chems.entry(id).or_insert(Chemical::new(id));
+        let val = self.entry(id);
         if let Some(new) = val.concnt().checked_sub(amount) {
             *val = Chemical::with_concentration(id, new);
             true
@@ -48,6 +138,136 @@ impl ChemoBody {
     }
 }
 
+// Only the chemicals that are present get written out, as a map keyed by id.
+impl Encodable for ChemoBody {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        let chems: BTreeMap<Id, Chemical> =
+            self.chems.iter().filter_map(|chem| chem.map(|c| (c.id, c))).collect();
+        s.emit_struct("ChemoBody", 1, |s| s.emit_struct_field("chems", 0, |s| chems.encode(s)))
+    }
+}
+
+impl Decodable for ChemoBody {
+    fn decode<D: Decoder>(d: &mut D) -> Result<ChemoBody, D::Error> {
+        d.read_struct("ChemoBody", 1, |d| {
+            let chems: HashMap<Id, Chemical> =
+                try!(d.read_struct_field("chems", 0, |d| Decodable::decode(d)));
+            let mut body = ChemoBody::new();
+            for (id, chem) in chems.into_iter() {
+                body.chems[id as usize] = Some(Chemical::with_concentration(id, chem.concnt()));
+            }
+            Ok(body)
+        })
+    }
+}
+
+/// A `DeltaMap` laid out like a `ChemoBody`, with a slot for every possible id, for collecting
+/// changes without hashing or allocating. It remembers which slots it has touched, so clearing and
+/// applying it only visit those, and once it has grown to fit a step's changes it can be cleared
+/// and reused from step to step without allocating again.
+pub struct DeltaBuffer {
+    deltas: [i16; 256],
+    touched: Vec<Id>,
+}
+
+impl DeltaBuffer {
+    pub fn new() -> DeltaBuffer {
+        DeltaBuffer { deltas: [0; 256], touched: Vec::new() }
+    }
+
+    pub fn add(&mut self, id: Id, delta: i16) {
+        if delta == 0 { return }
+        if self.deltas[id as usize] == 0 && !self.touched.contains(&id) {
+            self.touched.push(id);
+        }
+        self.deltas[id as usize] += delta;
+    }
+
+    pub fn get(&self, id: Id) -> i16 {
+        self.deltas[id as usize]
+    }
+
+    /// The chemicals changed so far, in the order they were first touched. Chemicals whose changes
+    /// have cancelled out are still listed, and `get` gives 0 for them.
+    pub fn touched(&self) -> &[Id] {
+        &self.touched
+    }
+
+    pub fn clear(&mut self) {
+        for &id in self.touched.iter() {
+            self.deltas[id as usize] = 0;
+        }
+        self.touched.clear();
+    }
+
+    /// Adds each change to its chemical like `ChemoBody::apply`.
+    pub fn apply_to(&self, body: &mut ChemoBody) {
+        for &id in self.touched.iter() {
+            let delta = self.deltas[id as usize];
+            if delta > 0 {
+                body.gain(id, min(delta, 255) as u8);
+            } else if delta < 0 {
+                body.drain(id, min(-delta, 255) as u8);
+            }
+        }
+    }
+}
+
+impl Default for DeltaBuffer {
+    fn default() -> DeltaBuffer {
+        DeltaBuffer::new()
+    }
+}
+
+/// Pairs a chemical with a buffer that soaks up part of every change to it. On each tick, a
+/// `fraction` (out of 255) of the chemical's net rise is moved into `buffer` as long as the buffer
+/// holds less than `capacity`, and the same fraction of a net fall is paid back out of the buffer.
+#[derive(Copy, Clone)]
+pub struct Buffer {
+    pub chemical: Id,
+    pub buffer: Id,
+    pub fraction: u8,
+    pub capacity: Concentration,
+}
+
+impl Buffer {
+    pub fn new(chemical: Id, buffer: Id, fraction: u8, capacity: Concentration) -> Buffer {
+        Buffer { chemical: chemical, buffer: buffer, fraction: fraction, capacity: capacity }
+    }
+
+    pub fn apply(&self, body: &mut ChemoBody, before: Concentration) {
+        let after = body.concnt(self.chemical);
+        let modifier = self.fraction as f32 / 255.0;
+        if after > before {
+            let room = self.capacity.saturating_sub(body.concnt(self.buffer));
+            let absorbed = min(((after - before) as f32 * modifier) as u8, room);
+            body.lose(self.chemical, absorbed);
+            body.gain(self.buffer, absorbed);
+        } else if after < before {
+            let stored = body.concnt(self.buffer);
+            let released = min(((before - after) as f32 * modifier) as u8, stored);
+            body.lose(self.buffer, released);
+            body.gain(self.chemical, released);
+        }
+    }
+}
+
+/// Emitters that draw on a shared production budget. Whenever the gains of the members firing in
+/// a step add up to more than `budget`, each of them is scaled down by the same proportion so
+/// that together they put out no more than the budget.
+#[derive(Clone)]
+pub struct EmitterGroup {
+    /// The indices of the member emitters among the genome's genes.
+    pub genes: Vec<usize>,
+    pub budget: Concentration,
+}
+
+impl EmitterGroup {
+    pub fn new(genes: Vec<usize>, budget: Concentration) -> EmitterGroup {
+        EmitterGroup { genes: genes, budget: budget }
+    }
+}
+
 #[derive(Copy, Clone, RustcEncodable, RustcDecodable)]
 pub struct Chemical {
     id: Id,
@@ -94,37 +314,106 @@ impl Rand for IoType {
     }
 }
 
+/// The time since a gene last fired, measured in ticks. Genes take their rate off it every time
+/// they fire and keep what is left over towards the next firing, so it stays below the rate
+/// between steps and no time is lost to steps that do not divide the rate evenly. Being a float,
+/// it has room to spare even at a rate of 255, and whole-tick steps count exactly.
 #[derive(Clone, RustcEncodable, RustcDecodable)]
-pub struct TickCount(Cell<u8>);
+pub struct TickCount(Cell<f32>);
 
 impl TickCount {
     fn new() -> TickCount {
-        TickCount(Cell::new(0))
+        TickCount(Cell::new(0.0))
+    }
+
+    /// Moves the count on by `dt` and gives how many times a gene that fires every `rate` ticks
+    /// fires, taking off the time those firings used.
+    fn fire(&self, dt: f32, rate: u8) -> u32 {
+        let (fired, left) = firings(self.0.get() + dt, rate);
+        self.0.set(left);
+        fired
+    }
+
+    fn val(&self) -> f32 {
+        self.0.get()
+    }
+}
+
+/// How many times a gene that fires every `rate` ticks fires once `tick` ticks have passed since
+/// it last did, along with the time left over towards its next firing. A rate of 0 fires once every
+/// step.
+fn firings(tick: f32, rate: u8) -> (u32, f32) {
+    if rate == 0 { return (1, 0.0) }
+    let fired = (tick / rate as f32).floor();
+    if fired < 1.0 { (0, tick) } else { (fired as u32, tick - fired * rate as f32) }
+}
+
+/// A running total of how much a reaction has used up. It is not saved: it is written out as
+/// nothing and always loads as zero.
+#[derive(Clone)]
+pub struct Throughput(Cell<f32>);
+
+impl Throughput {
+    fn new() -> Throughput {
+        Throughput(Cell::new(0.0))
     }
 
-    fn inc(&self) {
-        self.0.set(self.0.get() + 1);
+    fn add(&self, amount: f32) {
+        self.0.set(self.0.get() + amount);
     }
 
     fn zero(&self) {
-        self.0.set(0);
+        self.0.set(0.0);
     }
 
-    fn val(&self) -> u8 {
+    fn val(&self) -> f32 {
         self.0.get()
     }
 }
 
+impl Encodable for Throughput {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_nil()
+    }
+}
+
+impl Decodable for Throughput {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Throughput, D::Error> {
+        try!(d.read_nil());
+        Ok(Throughput::new())
+    }
+}
+
+/// A per-age gain schedule for an emitter. It holds one gain for each life stage, in the order
+/// `Baby`, `Child`, `Adolescent`, `Youth`, `Adult`, `Old`, `Senile`, so `[40, 0, 0, 0, 0, 0, 0]`
+/// is an emitter that only works during infancy. The default schedule has no gain at any age.
+#[derive(Copy, Clone, Default, RustcEncodable, RustcDecodable)]
+pub struct AgeSchedule(pub [Concentration; 7]);
+
+impl AgeSchedule {
+    pub fn gain(&self, age: Age) -> Concentration {
+        self.0[age as usize]
+    }
+}
+
 #[derive(Clone, RustcEncodable, RustcDecodable)]
 pub struct Emitter {
     pub kind: IoType,
     pub chemical: Id,
     pub rate: u8,
+    /// How much a digital emitter makes when it fires. An analogue emitter makes `gain / 255` of
+    /// how far its locus is from the threshold instead, so it never makes more than the locus can
+    /// move. Being a concentration it cannot be NaN or infinite, and needs no checking.
     pub gain: Concentration,
     pub locus: LocusId,
     pub threshold: LocusValue,
     pub clear_after_read: bool,
     pub invert: bool,
+    /// Replaces `gain` with an age-dependent gain when present.
+    pub schedule: Option<AgeSchedule>,
+    /// Makes the gain a fraction of the total of these chemicals, out of 255, instead of an amount,
+    /// so that the emitter keeps its chemical in proportion to the group.
+    pub relative_to: Option<Vec<Id>>,
     pub tick: TickCount,
 }
 
@@ -141,38 +430,147 @@ impl Emitter {
         Emitter {
             kind: kind, chemical: chemical, rate: rate, gain: gain, locus: locus,
             threshold: threshold, clear_after_read: clear_after_read, invert: invert,
-            tick: TickCount::new()
+            schedule: None, relative_to: None, tick: TickCount::new()
         }
     }
 
-    pub fn step(&self, creature: &mut Creature) {
-        self.tick.inc();
-        if self.tick.val() < self.rate { return }
-        self.tick.zero();
+    /// The gain the emitter works with at the creature's current age.
+    pub fn current_gain(&self, creature: &Creature) -> Concentration {
+        let gain = match self.schedule {
+            Some(ref schedule) => schedule.gain(creature.age()),
+            None => self.gain,
+        };
+        match self.relative_to {
+            Some(ref group) => {
+                let body = creature.chemo_body();
+                let total = group.iter().map(|&id| body.peek(id) as u32).sum::<u32>();
+                min(gain as u32 * total / 255, 255) as u8
+            },
+            None => gain,
+        }
+    }
+
+    /// Whether the emitter will fire in the next step taken with `dt`.
+    pub fn fires(&self, dt: f32) -> bool {
+        self.tick.val() + dt >= self.rate as f32
+    }
+
+    pub fn step(&self, creature: &mut Creature, ctx: &StepContext) {
+        self.step_scaled(creature, ctx, 1.0)
+    }
+
+    /// Steps like `step`, with the gain multiplied by `scale`.
+    pub fn step_scaled(&self, creature: &mut Creature, ctx: &StepContext, scale: f32) {
+        for _ in 0 .. self.tick.fire(ctx.dt, self.rate) {
+            self.emit(creature, scale);
+        }
+    }
+
+    fn emit(&self, creature: &mut Creature, scale: f32) {
         let signal = if self.invert {
             255 - creature.get_locus(self.locus)
         } else {
             creature.get_locus(self.locus)
         };
+        let gain = (self.current_gain(creature) as f32 * scale) as u8;
         let mut body = creature.chemo_body_mut();
+        let before: Option<Vec<_>> = if cfg!(debug_assertions) {
+            Some(body.chems.iter().filter_map(|chem| chem.as_ref())
+                 .filter(|c| c.id != self.chemical).map(|c| (c.id, c.concnt())).collect())
+        } else {
+            None
+        };
         match self.kind {
             IoType::Analogue => {
-                let modifier = self.gain as f32 / 255.0;
+                let modifier = gain as f32 / 255.0;
                 if signal >= self.threshold {
                     let output = ((signal - self.threshold) as f32 * modifier) as u8;
                     body.gain(self.chemical, output);
                 } else {
                     let output = ((self.threshold - signal) as f32 * modifier) as u8;
-                    if !body.lose(self.chemical, output) {
-                        let concnt = body.concnt(self.chemical);
-                        body.lose(self.chemical, concnt);
-                    }
+                    body.drain(self.chemical, output);
                 }
             },
             IoType::Digital => {
-                body.gain(self.chemical, if signal >= self.threshold { self.gain } else { 0 });
+                body.gain(self.chemical, if signal >= self.threshold { gain } else { 0 });
             }
         }
+        if let Some(before) = before {
+            debug_assert!(before.iter().all(|&(id, concnt)| body.peek(id) == concnt),
+                          "Emitter changed a chemical other than its own.");
+        }
+    }
+}
+
+impl Steppable for Emitter {
+    fn step(&self, creature: &mut Creature, ctx: &StepContext) {
+        Emitter::step(self, creature, ctx)
+    }
+}
+
+/// Holds a chemical near a set level, as regulatory feedback would. Every step it makes or removes
+/// `rate` of the difference between the chemical's concentration and `target` (rounded to the
+/// nearest whole amount), so it pulls the chemical up from below and down from above alike. The
+/// rate is per tick and is scaled by the step's `dt`, closing the whole gap at most.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct Homeostat {
+    pub chemical: Id,
+    pub target: f32,
+    pub rate: f32,
+}
+
+impl Homeostat {
+    pub fn new(chemical: Id, target: f32, rate: f32) -> Homeostat {
+        Homeostat { chemical: chemical, target: target, rate: rate }
+    }
+
+    pub fn step(&self, creature: &mut Creature, ctx: &StepContext) {
+        let mut body = creature.chemo_body_mut();
+        let gap = self.target - body.peek(self.chemical) as f32;
+        let pull = (gap * (self.rate * ctx.dt).min(1.0)).round();
+        if pull > 0.0 {
+            body.gain(self.chemical, pull.min(255.0) as u8);
+        } else if pull < 0.0 {
+            body.drain(self.chemical, (-pull).min(255.0) as u8);
+        }
+    }
+}
+
+impl Steppable for Homeostat {
+    fn step(&self, creature: &mut Creature, ctx: &StepContext) {
+        Homeostat::step(self, creature, ctx)
+    }
+}
+
+/// Moves one chemical into another, as transport would. Every step it moves `rate` of what there
+/// is of `from` (rounded down) into `to`, scaled by the step's `dt` like a homeostat's rate. What
+/// leaves `from` always arrives in `to`, so the two always add up to the same amount: when `to`
+/// has too little room left, only as much as fits is moved.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct Exchange {
+    pub from: Id,
+    pub to: Id,
+    pub rate: f32,
+}
+
+impl Exchange {
+    pub fn new(from: Id, to: Id, rate: f32) -> Exchange {
+        Exchange { from: from, to: to, rate: rate }
+    }
+
+    pub fn step(&self, creature: &mut Creature, ctx: &StepContext) {
+        if self.from == self.to { return }
+        let mut body = creature.chemo_body_mut();
+        let share = (self.rate * ctx.dt).max(0.0).min(1.0);
+        let amount = min((body.peek(self.from) as f32 * share) as u8, 255 - body.peek(self.to));
+        body.drain(self.from, amount);
+        body.gain(self.to, amount);
+    }
+}
+
+impl Steppable for Exchange {
+    fn step(&self, creature: &mut Creature, ctx: &StepContext) {
+        Exchange::step(self, creature, ctx)
     }
 }
 
@@ -188,26 +586,126 @@ pub enum ReactionType {
     Catalytic(Chemical, Chemical, Chemical),
     /// A + B -> A
     CatalyticBreakdown(Chemical, Chemical),
+    /// A -> B + C
+    Split(Chemical, Chemical, Chemical),
+    /// A + B -> 2B, where B is needed to make more of itself. With `Kinetics::MassAction` it runs
+    /// in proportion to both, so B grows slowly at first, then faster, then levels off as A runs
+    /// out.
+    Autocatalytic(Chemical, Chemical),
 }
 
 impl Rand for ReactionType {
     fn rand<R: Rng>(rng: &mut R) -> ReactionType {
         let chem = |rng: &mut R| Chemical::with_concentration(rng.gen(), rng.gen_range(1, 17));
-        match rng.gen_range(0, 5) {
+        match rng.gen_range(0, 7) {
             1 => ReactionType::Normal(chem(rng), chem(rng), chem(rng), chem(rng)),
             2 => ReactionType::Fusion(chem(rng), chem(rng), chem(rng)),
             3 => ReactionType::Decay(chem(rng)),
             4 => ReactionType::Catalytic(chem(rng), chem(rng), chem(rng)),
+            5 => ReactionType::Split(chem(rng), chem(rng), chem(rng)),
+            6 => ReactionType::Autocatalytic(chem(rng), chem(rng)),
             _ => ReactionType::CatalyticBreakdown(chem(rng), chem(rng))
         }
     }
 }
 
-#[derive(Clone, RustcEncodable, RustcDecodable)]
+/// What a genome editor needs to know about a kind of reaction. Catalysts are counted apart from
+/// reactants, since they are not used up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReactionKindInfo {
+    pub name: &'static str,
+    pub reactants: usize,
+    pub catalysts: usize,
+    pub products: usize,
+}
+
+static REACTION_KINDS: [ReactionKindInfo; 7] = [
+    ReactionKindInfo { name: "Normal", reactants: 2, catalysts: 0, products: 2 },
+    ReactionKindInfo { name: "Fusion", reactants: 2, catalysts: 0, products: 1 },
+    ReactionKindInfo { name: "Decay", reactants: 1, catalysts: 0, products: 0 },
+    ReactionKindInfo { name: "Catalytic", reactants: 1, catalysts: 1, products: 1 },
+    ReactionKindInfo { name: "CatalyticBreakdown", reactants: 1, catalysts: 1, products: 0 },
+    ReactionKindInfo { name: "Split", reactants: 1, catalysts: 0, products: 2 },
+    ReactionKindInfo { name: "Autocatalytic", reactants: 1, catalysts: 1, products: 1 },
+];
+
+impl ReactionType {
+    /// Every kind of reaction, in the order the variants are declared.
+    pub fn variants() -> &'static [ReactionKindInfo] {
+        &REACTION_KINDS
+    }
+
+    /// Every chemical the reaction mentions, catalysts included, in the order they are written.
+    pub fn chemicals(&self) -> Vec<&Chemical> {
+        match *self {
+            ReactionType::Normal(ref a, ref b, ref c, ref d) => vec![a, b, c, d],
+            ReactionType::Fusion(ref a, ref b, ref c) => vec![a, b, c],
+            ReactionType::Decay(ref a) => vec![a],
+            ReactionType::Catalytic(ref a, ref b, ref c) => vec![a, b, c],
+            ReactionType::CatalyticBreakdown(ref a, ref b) => vec![a, b],
+            ReactionType::Split(ref a, ref b, ref c) => vec![a, b, c],
+            ReactionType::Autocatalytic(ref a, ref b) => vec![a, b],
+        }
+    }
+
+    /// The chemicals that must be present for the reaction to run, catalysts included.
+    pub fn inputs(&self) -> Vec<&Chemical> {
+        match *self {
+            ReactionType::Normal(ref a, ref b, _, _) |
+            ReactionType::Fusion(ref a, ref b, _) |
+            ReactionType::Catalytic(ref a, ref b, _) |
+            ReactionType::CatalyticBreakdown(ref a, ref b) |
+            ReactionType::Autocatalytic(ref a, ref b) => vec![a, b],
+            ReactionType::Decay(ref a) |
+            ReactionType::Split(ref a, _, _) => vec![a],
+        }
+    }
+
+    pub fn map_chemicals<F: Fn(Id) -> Id>(&self, f: F) -> ReactionType {
+        let m = |c: &Chemical| Chemical::with_concentration(f(c.id), c.concentration);
+        match *self {
+            ReactionType::Normal(ref a, ref b, ref c, ref d) =>
+                ReactionType::Normal(m(a), m(b), m(c), m(d)),
+            ReactionType::Fusion(ref a, ref b, ref c) => ReactionType::Fusion(m(a), m(b), m(c)),
+            ReactionType::Decay(ref a) => ReactionType::Decay(m(a)),
+            ReactionType::Catalytic(ref a, ref b, ref c) =>
+                ReactionType::Catalytic(m(a), m(b), m(c)),
+            ReactionType::CatalyticBreakdown(ref a, ref b) =>
+                ReactionType::CatalyticBreakdown(m(a), m(b)),
+            ReactionType::Split(ref a, ref b, ref c) => ReactionType::Split(m(a), m(b), m(c)),
+            ReactionType::Autocatalytic(ref a, ref b) => ReactionType::Autocatalytic(m(a), m(b)),
+        }
+    }
+}
+
+/// The rate law that decides how many times a reaction runs when it fires.
+#[derive(Copy, Clone, RustcEncodable, RustcDecodable)]
+pub enum Kinetics {
+    /// As many times as the scarcest reactant allows.
+    LimitingReagent,
+    /// `k` times the product of the concentrations of its inputs (catalysts included, each out of
+    /// 255 and counted once for every time it is listed), rounded down. It never runs more times
+    /// than `LimitingReagent` would.
+    MassAction(f32),
+}
+
+#[derive(Clone, RustcEncodable)]
 pub struct Reaction {
     pub kind: ReactionType,
     pub rate: u8,
+    /// Draws how many times the reaction runs from a normal distribution around the usual amount
+    /// (with a standard deviation of its square root) whenever the step has an RNG. Draws are cut
+    /// short at what the reactants allow, so a reaction that is already using up all of something
+    /// runs a little less than the usual amount on average.
+    pub stochastic: bool,
+    /// Makes catalytic reactions run faster the more catalyst there is: each firing converts the
+    /// catalyst's concentration out of 255 of the most the other reactant allows, so doubling the
+    /// catalyst doubles the conversion. The catalyst's own coefficient is then ignored. Other kinds
+    /// of reaction are unaffected.
+    pub catalyst_scaled: bool,
+    pub kinetics: Kinetics,
     pub tick: TickCount,
+    pub throughput: Throughput,
 }
 
 impl Rand for Reaction {
@@ -218,114 +716,380 @@ impl Rand for Reaction {
 
 impl Reaction {
     pub fn new(kind: ReactionType, rate: u8) -> Reaction {
-        Reaction { kind: kind, rate: rate, tick: TickCount::new() }
+        Reaction {
+            kind: kind, rate: rate, stochastic: false, catalyst_scaled: false,
+            kinetics: Kinetics::LimitingReagent, tick: TickCount::new(),
+            throughput: Throughput::new()
+        }
     }
 
-    pub fn step(&self, creature: &mut Creature) {
-        self.tick.inc();
-        if self.tick.val() < self.rate { return }
-        self.tick.zero();
-        let mut body = creature.chemo_body_mut();
-        match self.kind {
-            ReactionType::Normal(ref a, ref b, ref c, ref d) => {
-                let n = min(body.concnt(a.id) / a.concnt(),
-                            body.concnt(b.id) / b.concnt());
-                let mut update = |c: &Chemical, add: bool| {
-                    let larger = n as u16 * c.concnt() as u16;
-                    let value = if larger > 255 {
-                        255
-                    } else {
-                        larger as u8
-                    };
-                    if add {
-                        body.gain(c.id, value)
-                    } else {
-                        body.lose(c.id, value)
-                    }
-                };
-                update(a, false);
-                update(b, false);
-                update(c, true);
-                update(d, true);
-            },
-            ReactionType::Fusion(ref a, ref b, ref c) => {
-                let n = min(body.concnt(a.id) / a.concnt(),
-                            body.concnt(b.id) / b.concnt());
-                let mut update = |c: &Chemical, add: bool| {
-                    let larger = n as u16 * c.concnt() as u16;
-                    let value = if larger > 255 {
-                        255
-                    } else {
-                        larger as u8
-                    };
-                    if add {
-                        body.gain(c.id, value)
-                    } else {
-                        body.lose(c.id, value)
-                    }
-                };
-                update(a, false);
-                update(b, false);
-                update(c, true);
-            },
-            ReactionType::Decay(ref a) => {
-                let n = body.concnt(a.id) / a.concnt();
-                let larger = n as u16 * a.concnt() as u16;
-                let value = if larger > 255 {
-                    255
+    /// Draws how many times a stochastic reaction runs when it would usually run `n` times, never
+    /// going over `most`.
+    fn sample(&self, n: u8, most: u8, ctx: &StepContext) -> u8 {
+        match ctx.rng {
+            Some(ref rng) if self.stochastic && n > 0 => {
+                let normal = Normal::new(n as f64, (n as f64).sqrt());
+                let draw = normal.ind_sample(&mut *rng.borrow_mut()).round();
+                if draw < 0.0 {
+                    0
+                } else if draw > most as f64 {
+                    most
                 } else {
-                    larger as u8
-                };
-                body.lose(a.id, value);
-            },
-            ReactionType::Catalytic(ref a, ref b, ref c) => {
-                let n = min(body.concnt(a.id) / a.concnt(),
-                            body.concnt(b.id) / b.concnt());
-                let mut update = |c: &Chemical, add: bool| {
-                    let larger = n as u16 * c.concnt() as u16;
-                    let value = if larger > 255 {
-                        255
-                    } else {
-                        larger as u8
-                    };
-                    if add {
-                        body.gain(c.id, value)
-                    } else {
-                        body.lose(c.id, value)
-                    }
-                };
-                update(b, false);
-                update(c, true);
+                    draw as u8
+                }
             },
-            ReactionType::CatalyticBreakdown(ref a, ref b) => {
-                let n = min(body.concnt(a.id) / a.concnt(),
-                            body.concnt(b.id) / b.concnt());
-                let larger = n as u16 * b.concnt() as u16;
-                let value = if larger > 255 {
-                    255
-                } else {
-                    larger as u8
-                };
-                body.lose(b.id, value);
+            _ => n
+        }
+    }
+
+    /// How many times the reaction can run on what is in `body`, limited by its scarcest reactant.
+    /// A chemical listed more than once on the left has its coefficients added up first, so
+    /// `A + A -> A + B` runs once for every two A's worth present. `kinetics` can size it
+    /// differently within that limit, and `catalyst_scaled` overrides both for catalytic reactions.
+    pub fn extent(&self, body: &ChemoBody) -> u8 {
+        let limit = self.room(body);
+        match self.kind {
+            ReactionType::Catalytic(ref a, _, _) |
+            ReactionType::CatalyticBreakdown(ref a, _) if self.catalyst_scaled =>
+                return (body.peek(a.id) as u16 * limit as u16 / 255) as u8,
+            _ => (),
+        }
+        match self.kinetics {
+            Kinetics::LimitingReagent => limit,
+            Kinetics::MassAction(k) => {
+                let rate = self.kind.inputs().iter().fold(k, |acc, c| {
+                    acc * body.peek(c.id) as f32 / 255.0
+                });
+                if rate < 0.0 { 0 } else { (rate as u16).min(limit as u16) as u8 }
             },
         }
     }
+
+    /// The most times the reaction can run on what is in `body` before something it needs runs
+    /// out, which `extent` never goes over. A catalyst-scaled reaction is only limited by what it
+    /// uses up. A reaction with a coefficient of 0 on the left cannot be sized, so it never runs.
+    fn room(&self, body: &ChemoBody) -> u8 {
+        match self.kind {
+            ReactionType::Catalytic(_, ref b, _) |
+            ReactionType::CatalyticBreakdown(_, ref b) if self.catalyst_scaled =>
+                return body.peek(b.id).checked_div(b.concentration).unwrap_or(0),
+            _ => (),
+        }
+        let inputs = self.kind.inputs();
+        let mut needs: Vec<(Id, u16)> = Vec::with_capacity(inputs.len());
+        for c in inputs.iter() {
+            match needs.iter().position(|&(id, _)| id == c.id) {
+                Some(i) => needs[i].1 += c.concentration as u16,
+                None => needs.push((c.id, c.concentration as u16)),
+            }
+        }
+        needs.iter().map(|&(id, need)| {
+            (body.peek(id) as u16).checked_div(need).unwrap_or(0)
+        }).min().unwrap_or(0) as u8
+    }
+
+    fn deltas(&self, n: u8) -> DeltaMap {
+        let mut deltas = DeltaMap::new();
+        self.each_delta(n, |id, delta| *deltas.entry(id).or_insert(0) += delta);
+        deltas
+    }
+
+    /// Calls `f` with the change to each chemical `n` runs of the reaction make, a chemical
+    /// listed more than once getting a call for each time.
+    fn each_delta<F: FnMut(Id, i16)>(&self, n: u8, mut f: F) {
+        let amount = |c: &Chemical| min(n as u16 * c.concentration as u16, 255) as i16;
+        for c in self.reactants() {
+            f(c.id, -amount(c));
+        }
+        for c in self.products() {
+            f(c.id, amount(c));
+        }
+    }
+
+    /// The chemicals a run of the reaction uses up, in the order they are written. Catalysts are
+    /// left out, as they come back unchanged.
+    pub fn reactants(&self) -> Vec<&Chemical> {
+        match self.kind {
+            ReactionType::Normal(ref a, ref b, _, _) |
+            ReactionType::Fusion(ref a, ref b, _) => vec![a, b],
+            ReactionType::Decay(ref a) |
+            ReactionType::Split(ref a, _, _) |
+            ReactionType::Autocatalytic(ref a, _) => vec![a],
+            ReactionType::Catalytic(_, ref b, _) |
+            ReactionType::CatalyticBreakdown(_, ref b) => vec![b],
+        }
+    }
+
+    /// The chemicals a run of the reaction makes, in the order they are written. Catalysts are
+    /// left out, as they were there to begin with, except that an autocatalytic reaction's product
+    /// is given once for the extra it makes.
+    pub fn products(&self) -> Vec<&Chemical> {
+        match self.kind {
+            ReactionType::Normal(_, _, ref c, ref d) |
+            ReactionType::Split(_, ref c, ref d) => vec![c, d],
+            ReactionType::Fusion(_, _, ref c) |
+            ReactionType::Catalytic(_, _, ref c) |
+            ReactionType::Autocatalytic(_, ref c) => vec![c],
+            ReactionType::Decay(_) |
+            ReactionType::CatalyticBreakdown(_, _) => vec![],
+        }
+    }
+
+    /// Like `reactants`, but lets the chemicals be changed in place.
+    pub fn reactants_mut(&mut self) -> Vec<&mut Chemical> {
+        match self.kind {
+            ReactionType::Normal(ref mut a, ref mut b, _, _) |
+            ReactionType::Fusion(ref mut a, ref mut b, _) => vec![a, b],
+            ReactionType::Decay(ref mut a) |
+            ReactionType::Split(ref mut a, _, _) |
+            ReactionType::Autocatalytic(ref mut a, _) => vec![a],
+            ReactionType::Catalytic(_, ref mut b, _) |
+            ReactionType::CatalyticBreakdown(_, ref mut b) => vec![b],
+        }
+    }
+
+    /// Like `products`, but lets the chemicals be changed in place.
+    pub fn products_mut(&mut self) -> Vec<&mut Chemical> {
+        match self.kind {
+            ReactionType::Normal(_, _, ref mut c, ref mut d) |
+            ReactionType::Split(_, ref mut c, ref mut d) => vec![c, d],
+            ReactionType::Fusion(_, _, ref mut c) |
+            ReactionType::Catalytic(_, _, ref mut c) |
+            ReactionType::Autocatalytic(_, ref mut c) => vec![c],
+            ReactionType::Decay(_) |
+            ReactionType::CatalyticBreakdown(_, _) => vec![],
+        }
+    }
+
+    /// The net change one run of the reaction makes to each chemical. Catalysts come out even, so
+    /// they are left out.
+    pub fn coefficients(&self) -> DeltaMap {
+        self.deltas(1)
+    }
+
+    /// Works out what the reaction would do to `body` if it fired now, without touching its tick
+    /// or the body itself. Stochastic reactions report their deterministic amount.
+    pub fn simulate_once(&self, body: &ChemoBody) -> DeltaMap {
+        self.deltas(self.extent(body))
+    }
+
+    /// Whether the reaction will fire in the next step taken with `dt`.
+    pub fn fires(&self, dt: f32) -> bool {
+        self.tick.val() + dt >= self.rate as f32
+    }
+
+    pub fn step(&self, creature: &mut Creature, ctx: &StepContext) {
+        self.step_limited(creature, ctx, 255)
+    }
+
+    /// Steps like `step`, but runs the reaction at most `limit` times each time it fires.
+    pub fn step_limited(&self, creature: &mut Creature, ctx: &StepContext, limit: u8) {
+        self.step_buffered(creature, ctx, limit, &mut DeltaBuffer::new())
+    }
+
+    /// Steps like `step_limited`, collecting the reaction's changes in `buffer` (which it clears
+    /// first) rather than in a fresh map.
+    pub fn step_buffered(&self, creature: &mut Creature, ctx: &StepContext, limit: u8,
+                         buffer: &mut DeltaBuffer) {
+        for _ in 0 .. self.tick.fire(ctx.dt, self.rate) {
+            self.run(creature, ctx, limit, buffer);
+        }
+    }
+
+    fn run(&self, creature: &mut Creature, ctx: &StepContext, limit: u8, buffer: &mut DeltaBuffer) {
+        let mut body = creature.chemo_body_mut();
+        let n = self.sample(min(self.extent(&body), limit), min(self.room(&body), limit), ctx);
+        trace!("Reaction fired, running {} times.", n);
+        buffer.clear();
+        self.each_delta(n, |id, delta| buffer.add(id, delta));
+        let used = buffer.touched().iter().filter(|&&id| buffer.get(id) < 0).map(|&id| {
+            min(-buffer.get(id), body.peek(id) as i16) as f32
+        }).sum::<f32>();
+        self.throughput.add(used);
+        buffer.apply_to(&mut body);
+    }
+
+    /// Steps like `step` without touching the reaction or the body: the time since the reaction
+    /// last fired is passed in as `tick` rather than kept in the reaction, and the new time is
+    /// handed back along with what the reaction does to `body` instead of being applied. Throughput
+    /// is not counted.
+    pub fn step_pure(&self, tick: f32, body: &ChemoBody, ctx: &StepContext) -> (f32, DeltaMap) {
+        let (fired, tick) = firings(tick + ctx.dt, self.rate);
+        let mut total = DeltaMap::new();
+        if fired == 0 { return (tick, total) }
+        let mut body = body.clone();
+        for _ in 0 .. fired {
+            let deltas = self.deltas(self.sample(self.extent(&body), self.room(&body), ctx));
+            body.apply(&deltas);
+            for (id, delta) in deltas {
+                *total.entry(id).or_insert(0) += delta;
+            }
+        }
+        (tick, total)
+    }
+
+    /// The total amount of reactants, catalysts aside, that the reaction has used up since it was
+    /// made, loaded or last reset.
+    pub fn throughput(&self) -> f32 {
+        self.throughput.val()
+    }
+
+    pub fn reset_throughput(&self) {
+        self.throughput.zero();
+    }
 }
 
-#[derive(Copy, Clone, RustcEncodable, RustcDecodable)]
+// Genomes saved before reactions could be stochastic, catalyst-scaled or follow mass action
+// leave those settings out, so they load with the ones `Reaction::new` gives.
+impl Decodable for Reaction {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Reaction, D::Error> {
+        d.read_struct("Reaction", 7, |d| {
+            let reaction = Reaction::new(try!(field(d, "kind", 0)), try!(field(d, "rate", 1)));
+            let stochastic: Option<bool> = try!(field(d, "stochastic", 2));
+            let catalyst_scaled: Option<bool> = try!(field(d, "catalyst_scaled", 3));
+            let kinetics: Option<Kinetics> = try!(field(d, "kinetics", 4));
+            Ok(Reaction {
+                stochastic: stochastic.unwrap_or(reaction.stochastic),
+                catalyst_scaled: catalyst_scaled.unwrap_or(reaction.catalyst_scaled),
+                kinetics: kinetics.unwrap_or(reaction.kinetics),
+                tick: try!(field(d, "tick", 5)),
+                throughput: try!(field(d, "throughput", 6)),
+                .. reaction
+            })
+        })
+    }
+}
+
+impl Steppable for Reaction {
+    fn step(&self, creature: &mut Creature, ctx: &StepContext) {
+        Reaction::step(self, creature, ctx)
+    }
+}
+
+/// Reactions that share one rate and tick counter, so they always fire on the same steps. Each time
+/// the group fires, its reactions run one after another in order, each sized like a plain
+/// `Reaction` on what the ones before it left.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct ReactionGroup {
+    pub kinds: Vec<ReactionType>,
+    pub rate: u8,
+    pub tick: TickCount,
+    pub throughput: Throughput,
+}
+
+impl ReactionGroup {
+    pub fn new(kinds: Vec<ReactionType>, rate: u8) -> ReactionGroup {
+        ReactionGroup {
+            kinds: kinds, rate: rate, tick: TickCount::new(), throughput: Throughput::new()
+        }
+    }
+
+    /// The group's reactions as plain reactions with its rate, each with a tick counter of its
+    /// own.
+    pub fn reactions(&self) -> Vec<Reaction> {
+        self.kinds.iter().map(|&kind| Reaction::new(kind, self.rate)).collect()
+    }
+
+    pub fn step(&self, creature: &mut Creature, ctx: &StepContext) {
+        let mut body = creature.chemo_body_mut();
+        for _ in 0 .. self.tick.fire(ctx.dt, self.rate) {
+            for reaction in self.reactions() {
+                let deltas = reaction.simulate_once(&body);
+                let used = deltas.iter().filter(|&(_, &delta)| delta < 0).map(|(&id, &delta)| {
+                    min(-delta, body.peek(id) as i16) as f32
+                }).sum::<f32>();
+                self.throughput.add(used);
+                body.apply(&deltas);
+            }
+        }
+    }
+
+    /// The total amount of reactants, catalysts aside, that the group's reactions have used up
+    /// between them since it was made, loaded or last reset.
+    pub fn throughput(&self) -> f32 {
+        self.throughput.val()
+    }
+
+    pub fn reset_throughput(&self) {
+        self.throughput.zero();
+    }
+}
+
+impl Steppable for ReactionGroup {
+    fn step(&self, creature: &mut Creature, ctx: &StepContext) {
+        ReactionGroup::step(self, creature, ctx)
+    }
+}
+
+/// What a receptor measures.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub enum ReceptorType {
+    /// The concentration of the receptor's chemical.
+    Single,
+    /// The product of the receptor's chemical and another, scaled back into 0-255, so it only
+    /// rises when both are present.
+    Coincidence(Id),
+    /// A weighted sum of concentrations, clamped into 0-255. The receptor's own chemical is only
+    /// counted if it is one of the terms.
+    Linear(Vec<(Id, f32)>),
+}
+
+impl Rand for ReceptorType {
+    fn rand<R: Rng>(rng: &mut R) -> ReceptorType {
+        match rng.gen_range(0, 3) {
+            1 => ReceptorType::Coincidence(rng.gen()),
+            2 => ReceptorType::Linear(vec![(rng.gen(), rng.gen_range(-2.0, 2.0)),
+                                           (rng.gen(), rng.gen_range(-2.0, 2.0))]),
+            _ => ReceptorType::Single,
+        }
+    }
+}
+
+impl ReceptorType {
+    pub fn map_chemicals<F: Fn(Id) -> Id>(&self, f: F) -> ReceptorType {
+        match *self {
+            ReceptorType::Single => ReceptorType::Single,
+            ReceptorType::Coincidence(other) => ReceptorType::Coincidence(f(other)),
+            ReceptorType::Linear(ref terms) =>
+                ReceptorType::Linear(terms.iter().map(|&(id, weight)| (f(id), weight)).collect()),
+        }
+    }
+}
+
+#[derive(Clone, RustcEncodable)]
 pub struct Receptor {
     pub kind: IoType,
+    pub sense: ReceptorType,
     pub chemical: Id,
     pub locus: LocusId,
     pub nominal: LocusValue,
     pub gain: LocusValue,
     pub threshold: Concentration,
-    pub invert: bool
+    pub invert: bool,
+    /// Makes the receptor report crossings of its threshold instead of levels. It writes its
+    /// nominal value plus its gain on the step what it senses rises above the threshold, minus its
+    /// gain on the step it falls back to or below, and its nominal value otherwise. Either
+    /// crossing counts as firing.
+    pub edge: bool,
+    /// What the receptor sensed on its last step.
+    pub last: Cell<Concentration>,
+    /// How many steps the receptor rests for after it fires, writing its nominal value whatever
+    /// it senses.
+    pub refractory: u8,
+    /// The steps left until the receptor can fire again.
+    pub resting: Cell<u8>,
+    /// Makes the receptor sense what it measures as a fraction of the total of these chemicals,
+    /// scaled so that the whole total is 255, rather than as an amount. With no total it senses 0.
+    pub relative_to: Option<Vec<Id>>,
 }
 
 impl Rand for Receptor {
     fn rand<R: Rng>(rng: &mut R) -> Receptor {
-        Receptor::new(rng.gen(), rng.gen(), rng.gen(), rng.gen(), rng.gen(), rng.gen(), rng.gen())
+        Receptor {
+            sense: rng.gen(),
+            .. Receptor::new(rng.gen(), rng.gen(), rng.gen(), rng.gen(), rng.gen(), rng.gen(),
+                             rng.gen())
+        }
     }
 }
 
@@ -333,15 +1097,87 @@ impl Receptor {
     pub fn new(kind: IoType, chemical: Id, locus: LocusId, nominal: LocusValue, gain: LocusValue,
                threshold: Concentration, invert: bool) -> Receptor {
         Receptor {
-            kind: kind, chemical: chemical, locus: locus, nominal: nominal, gain: gain,
-            threshold: threshold, invert: invert
+            kind: kind, sense: ReceptorType::Single, chemical: chemical, locus: locus,
+            nominal: nominal, gain: gain,
+            threshold: threshold, invert: invert, edge: false, last: Cell::new(0), refractory: 0,
+            resting: Cell::new(0), relative_to: None
+        }
+    }
+
+    /// What the receptor senses, given a way to look up concentrations.
+    pub fn measure<F: FnMut(Id) -> Concentration>(&self, mut concnt: F) -> Concentration {
+        let own = concnt(self.chemical);
+        let val = match self.sense {
+            ReceptorType::Single => own,
+            ReceptorType::Coincidence(other) => (own as u16 * concnt(other) as u16 / 255) as u8,
+            ReceptorType::Linear(ref terms) => {
+                let sum = terms.iter().fold(0.0, |acc, &(id, weight)| {
+                    acc + weight * concnt(id) as f32
+                });
+                if sum > 255.0 {
+                    255
+                } else if sum < 0.0 {
+                    0
+                } else {
+                    sum as u8
+                }
+            },
+        };
+        match self.relative_to {
+            Some(ref group) => {
+                let total = group.iter().map(|&id| concnt(id) as u32).sum::<u32>();
+                if total == 0 { 0 } else { min(val as u32 * 255 / total, 255) as u8 }
+            },
+            None => val,
         }
     }
 
+    /// Whether `val` is above the threshold, which is what makes a receptor fire unless it is an
+    /// edge receptor.
+    pub fn fires(&self, val: Concentration) -> bool {
+        val > self.threshold
+    }
+
     pub fn step(&self, creature: &mut Creature) {
-        let val = creature.chemo_body_mut().concnt(self.chemical);
+        let val = {
+            let mut body = creature.chemo_body_mut();
+            self.measure(|id| body.concnt(id))
+        };
+        let (output, resting, fired) = self.respond(self.last.get(), val, self.resting.get());
+        if fired {
+            trace!("Receptor on chemical {} fired, writing {} to locus {}.", self.chemical, output,
+                   self.locus);
+        }
+        self.last.set(val);
+        self.resting.set(resting);
+        creature.set_locus(self.locus, output);
+    }
+
+    /// How the receptor responds to sensing `val`, having sensed `last` the step before and with
+    /// `resting` steps of rest left: what it writes to its locus, the rest it has left afterwards
+    /// and whether it fired.
+    pub fn respond(&self, last: Concentration, val: Concentration, resting: u8)
+        -> (LocusValue, u8, bool) {
+        if resting > 0 { return (self.nominal, resting - 1, false) }
+        let (output, fired) = if self.edge {
+            let crossing = self.fires(val) as i16 - self.fires(last) as i16;
+            (self.edge_output(crossing), crossing != 0)
+        } else {
+            (self.output(val), self.fires(val))
+        };
+        (output, if fired { self.refractory } else { 0 }, fired)
+    }
+
+    /// The value an edge receptor writes for a `crossing` of 1 (rising), -1 (falling) or 0.
+    fn edge_output(&self, crossing: i16) -> LocusValue {
+        let r = if self.invert { -1 } else { 1 };
+        max(0, min(self.nominal as i16 + crossing * self.gain as i16 * r, 255)) as u8
+    }
+
+    /// The value the receptor writes to its locus when it senses `val`.
+    pub fn output(&self, val: Concentration) -> LocusValue {
         let r = if self.invert { -1 } else { 1 };
-        let output = match self.kind {
+        match self.kind {
             IoType::Analogue => {
                 let r = r as f32;
                 let modifier = self.gain as f32 / 255.0;
@@ -356,7 +1192,7 @@ impl Receptor {
                 }
             },
             IoType::Digital => {
-                let value = if val > self.threshold { self.gain as i16 } else { 0 } * r;
+                let value = if self.fires(val) { self.gain as i16 } else { 0 } * r;
                 let larger = self.nominal as i16 + value;
                 if larger > 255 {
                     255
@@ -366,7 +1202,675 @@ impl Receptor {
                     larger as u8
                 }
             }
+        }
+    }
+}
+
+// Genomes saved before receptors could sense more than their own chemical, report crossings or
+// rest leave those settings out, so they load with the ones `Receptor::new` gives.
+impl Decodable for Receptor {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Receptor, D::Error> {
+        d.read_struct("Receptor", 13, |d| {
+            let receptor = Receptor::new(try!(field(d, "kind", 0)), try!(field(d, "chemical", 2)),
+                                         try!(field(d, "locus", 3)), try!(field(d, "nominal", 4)),
+                                         try!(field(d, "gain", 5)), try!(field(d, "threshold", 6)),
+                                         try!(field(d, "invert", 7)));
+            let sense: Option<ReceptorType> = try!(field(d, "sense", 1));
+            let edge: Option<bool> = try!(field(d, "edge", 8));
+            let last: Option<Concentration> = try!(field(d, "last", 9));
+            let refractory: Option<u8> = try!(field(d, "refractory", 10));
+            let resting: Option<u8> = try!(field(d, "resting", 11));
+            Ok(Receptor {
+                sense: sense.unwrap_or(receptor.sense.clone()),
+                edge: edge.unwrap_or(receptor.edge),
+                last: Cell::new(last.unwrap_or(receptor.last.get())),
+                refractory: refractory.unwrap_or(receptor.refractory),
+                resting: Cell::new(resting.unwrap_or(receptor.resting.get())),
+                relative_to: try!(field(d, "relative_to", 12)),
+                .. receptor
+            })
+        })
+    }
+}
+
+impl Steppable for Receptor {
+    fn step(&self, creature: &mut Creature, _: &StepContext) {
+        Receptor::step(self, creature)
+    }
+}
+
+/// Reads one field of a struct. A missing field reads as `None` if `T` is an `Option`, and is an
+/// error otherwise.
+fn field<D: Decoder, T: Decodable>(d: &mut D, name: &str, idx: usize) -> Result<T, D::Error> {
+    d.read_struct_field(name, idx, |d| Decodable::decode(d))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use rand::{SeedableRng, StdRng};
+    use rustc_serialize::json::encode;
+    use super::*;
+
+    fn body_with(concentrations: &[(Id, Concentration)]) -> ChemoBody {
+        let mut body = ChemoBody::new();
+        for &(id, concnt) in concentrations.iter() {
+            body.gain(id, concnt);
+        }
+        body
+    }
+
+    fn chem(id: Id, concnt: Concentration) -> Chemical {
+        Chemical::with_concentration(id, concnt)
+    }
+
+    /// Random changes to the first `ids` chemicals, as ids, amounts and whether they are gains.
+    fn random_changes(seed: usize, ids: Id, count: usize) -> Vec<(Id, Concentration, bool)> {
+        let mut rng: StdRng = SeedableRng::from_seed(&[seed][..]);
+        (0 .. count).map(|_| (rng.gen_range(0, ids), rng.gen(), rng.gen())).collect()
+    }
+
+    /// Makes the same changes to a map of the kind `ChemoBody` replaced.
+    fn change_map(map: &mut HashMap<Id, Concentration>, changes: &[(Id, Concentration, bool)]) {
+        for &(id, amount, gain) in changes.iter() {
+            let concnt = map.entry(id).or_insert(0);
+            *concnt = if gain {
+                concnt.saturating_add(amount)
+            } else {
+                concnt.saturating_sub(amount)
+            };
+        }
+    }
+
+    fn change_body(body: &mut ChemoBody, changes: &[(Id, Concentration, bool)]) {
+        for &(id, amount, gain) in changes.iter() {
+            if gain { body.gain(id, amount); } else { body.drain(id, amount); }
+        }
+    }
+
+    #[test]
+    fn scheduled_emitters_are_inert_once_adult() {
+        let emitter = Emitter {
+            schedule: Some(AgeSchedule([40, 40, 40, 40, 0, 0, 0])),
+            .. Emitter::new(IoType::Digital, 1, 0, 200, 0, 0, false, false)
         };
-        creature.set_locus(self.locus, output);
+        let mut creature = Creature::new();
+        for locus in 1 .. 7 {
+            creature.set_locus(locus, 1);
+        }
+        assert_eq!(creature.age(), Age::Baby);
+        emitter.step(&mut creature, &StepContext::new());
+        assert_eq!(creature.chemo_body_mut().concnt(1), 40);
+        creature.set_locus(4, 0);
+        assert_eq!(creature.age(), Age::Adult);
+        emitter.step(&mut creature, &StepContext::new());
+        assert_eq!(creature.chemo_body_mut().concnt(1), 40);
+    }
+
+    #[test]
+    fn coincidence_receptors_only_fire_when_both_chemicals_are_high() {
+        let receptor = Receptor {
+            sense: ReceptorType::Coincidence(2),
+            .. Receptor::new(IoType::Digital, 1, 5, 0, 1, 100, false)
+        };
+        for &(first, second, fired) in [(200, 200, 1), (200, 0, 0), (0, 200, 0), (200, 100, 0),
+                                         (40, 40, 0)].iter() {
+            let mut creature = Creature::new();
+            creature.chemo_body_mut().gain(1, first);
+            creature.chemo_body_mut().gain(2, second);
+            receptor.step(&mut creature);
+            assert_eq!(creature.get_locus(5), fired, "{} and {}", first, second);
+        }
+    }
+
+    #[test]
+    fn halving_dt_and_doubling_the_steps_ends_up_the_same() {
+        let run = |dt: f32, steps: usize| {
+            let emitter = Emitter::new(IoType::Digital, 1, 3, 5, 0, 0, false, false);
+            let mut creature = Creature::new();
+            let ctx = StepContext { dt: dt, .. StepContext::new() };
+            for _ in 0 .. steps {
+                emitter.step(&mut creature, &ctx);
+            }
+            let concnt = creature.chemo_body_mut().concnt(1);
+            concnt
+        };
+        // All of them cover 60 ticks, which is 20 firings of 5, give or take the last one for
+        // steps that do not add up exactly.
+        assert_eq!((run(1.0, 60), run(0.5, 120), run(0.25, 240)), (100, 100, 100));
+        let (long, short) = (run(0.4, 150), run(0.2, 300));
+        assert!((long as i16 - 100).abs() <= 5, "Steps of 0.4 ended at {}.", long);
+        assert!((short as i16 - 100).abs() <= 5, "Steps of 0.2 ended at {}.", short);
+    }
+
+    #[test]
+    fn linear_receptors_weigh_up_their_terms() {
+        let linear = ReceptorType::Linear(vec![(1, 2.0), (2, -1.0)]);
+        let level = Receptor {
+            sense: linear.clone(), .. Receptor::new(IoType::Analogue, 1, 1, 0, 255, 0, false)
+        };
+        let switch = Receptor {
+            sense: linear, .. Receptor::new(IoType::Digital, 1, 2, 10, 50, 100, false)
+        };
+        let sense = |a: Concentration, b: Concentration| {
+            let mut creature = Creature::new();
+            creature.inject(1, a);
+            creature.inject(2, b);
+            level.step(&mut creature);
+            switch.step(&mut creature);
+            (creature.get_locus(1), creature.get_locus(2))
+        };
+        assert_eq!(sense(80, 30), (130, 60));
+        assert_eq!(sense(60, 30), (90, 10));
+        assert_eq!(sense(10, 90), (0, 10));
+        assert_eq!(sense(200, 10), (255, 60));
+    }
+
+    #[test]
+    fn bodies_behave_like_hashed_maps() {
+        let changes = random_changes(9, 16, 10000);
+        let mut body = ChemoBody::new();
+        let mut map = HashMap::new();
+        for chunk in changes.chunks(7) {
+            change_body(&mut body, chunk);
+            change_map(&mut map, chunk);
+            assert_eq!(body.snapshot(), map);
+        }
+    }
+
+    // Run with `cargo test -- --ignored --nocapture` to compare timings.
+    #[test]
+    #[ignore]
+    fn bench_bodies_against_hashed_maps() {
+        let changes = random_changes(4, 64, 100000);
+        let start = Instant::now();
+        let mut body = ChemoBody::new();
+        for _ in 0 .. 20 {
+            change_body(&mut body, &changes);
+        }
+        let dense = start.elapsed();
+        let start = Instant::now();
+        let mut map = HashMap::new();
+        for _ in 0 .. 20 {
+            change_map(&mut map, &changes);
+        }
+        let hashed = start.elapsed();
+        assert_eq!(body.snapshot(), map);
+        println!("ChemoBody took {:?}, HashMap took {:?}.", dense, hashed);
+    }
+
+    #[test]
+    fn quantizing_snaps_to_the_nearest_level() {
+        let start = [(1, 12), (2, 13), (3, 130), (4, 255), (5, 0), (6, 38)];
+        let mut body = body_with(&start);
+        body.quantize(0);
+        assert_eq!(body.snapshot(), body_with(&start).snapshot());
+        // Levels of 10 are 25.5 apart: 0, 26, 51, 77, 102, 128 and so on up to 255.
+        body.quantize(10);
+        let snapped = [(1, 0), (2, 26), (3, 128), (4, 255), (5, 0), (6, 26)];
+        assert_eq!(body.snapshot(), body_with(&snapped).snapshot());
+    }
+
+    #[test]
+    fn simulating_a_reaction_touches_nothing_and_predicts_its_step() {
+        let reaction = Reaction::new(ReactionType::Fusion(chem(1, 2), chem(2, 1), chem(3, 1)), 3);
+        let mut creature = Creature::new();
+        creature.inject(1, 50);
+        creature.inject(2, 20);
+        let ctx = StepContext::new();
+        reaction.step(&mut creature, &ctx);
+        reaction.step(&mut creature, &ctx);
+        let before = creature.chemo_body().snapshot();
+        let deltas = reaction.simulate_once(&creature.chemo_body());
+        assert_eq!(creature.chemo_body().snapshot(), before);
+        let expected: DeltaMap = vec![(1, -40), (2, -20), (3, 20)].into_iter().collect();
+        assert_eq!(deltas, expected);
+        let mut body = body_with(&[(1, 50), (2, 20)]);
+        body.apply(&deltas);
+        reaction.step(&mut creature, &ctx);
+        assert_eq!(creature.chemo_body().snapshot(), body.snapshot());
+    }
+
+    #[test]
+    fn repeated_reactants_are_sized_by_their_combined_coefficient() {
+        let reaction = Reaction::new(ReactionType::Normal(chem(1, 1), chem(1, 1), chem(1, 1),
+                                                          chem(2, 1)), 0);
+        let body = body_with(&[(1, 11)]);
+        assert_eq!(reaction.extent(&body), 5);
+        let expected: DeltaMap = vec![(1, -5), (2, 5)].into_iter().collect();
+        assert_eq!(reaction.simulate_once(&body), expected);
+        let mut creature = Creature::new();
+        creature.inject(1, 11);
+        reaction.step(&mut creature, &StepContext::new());
+        assert_eq!((creature.chemo_body().peek(1), creature.chemo_body().peek(2)), (6, 5));
+    }
+
+    #[test]
+    fn doubling_the_catalyst_doubles_the_conversion() {
+        let reaction = Reaction {
+            catalyst_scaled: true,
+            .. Reaction::new(ReactionType::Catalytic(chem(1, 1), chem(2, 1), chem(3, 1)), 0)
+        };
+        let converted = |catalyst: Concentration| {
+            let mut creature = Creature::new();
+            creature.inject(1, catalyst);
+            creature.inject(2, 204);
+            reaction.step(&mut creature, &StepContext::new());
+            let body = creature.chemo_body();
+            assert_eq!(body.peek(1), catalyst);
+            body.peek(3)
+        };
+        assert_eq!((converted(50), converted(100), converted(200)), (40, 80, 160));
+    }
+
+    #[test]
+    fn the_slowest_reactions_fire_every_255_steps() {
+        let reaction = Reaction::new(ReactionType::Decay(chem(1, 1)), 255);
+        let mut creature = Creature::new();
+        let ctx = StepContext::new();
+        let mut fired = vec![];
+        for t in 1 .. 801 {
+            // Decay uses up everything there is, so top the chemical up before every step.
+            creature.inject(1, 255);
+            reaction.step(&mut creature, &ctx);
+            if creature.chemo_body().peek(1) == 0 {
+                fired.push(t);
+            }
+        }
+        assert_eq!(fired, vec![255, 510, 765]);
+    }
+
+    #[test]
+    fn pure_steps_match_steps_that_keep_their_own_tick() {
+        let reaction = Reaction::new(ReactionType::Fusion(chem(1, 2), chem(2, 1), chem(3, 1)), 3);
+        let ctx = StepContext { dt: 1.5, .. StepContext::new() };
+        let mut creature = Creature::new();
+        creature.inject(1, 200);
+        creature.inject(2, 50);
+        let mut body = body_with(&[(1, 200), (2, 50)]);
+        let mut tick = 0.0;
+        for _ in 0 .. 40 {
+            reaction.step(&mut creature, &ctx);
+            let (next, deltas) = reaction.step_pure(tick, &body, &ctx);
+            body.apply(&deltas);
+            tick = next;
+            assert_eq!(tick, reaction.tick.val());
+            assert_eq!(body.snapshot(), creature.concentrations());
+        }
+        assert!(body.peek(3) > 0);
+    }
+
+    #[test]
+    fn mass_action_runs_in_proportion_to_the_reactants_where_limiting_reagent_uses_them_up() {
+        let fusion = ReactionType::Fusion(chem(1, 1), chem(2, 1), chem(3, 1));
+        let with = |kinetics| Reaction { kinetics: kinetics, .. Reaction::new(fusion, 0) };
+        let run = |reaction: &Reaction, b: Concentration| {
+            let mut creature = Creature::new();
+            creature.inject(1, 200);
+            creature.inject(2, b);
+            reaction.step(&mut creature, &StepContext::new());
+            let body = creature.chemo_body();
+            (body.peek(1), body.peek(2), body.peek(3))
+        };
+        let limiting = with(Kinetics::LimitingReagent);
+        assert_eq!((run(&limiting, 100), run(&limiting, 50)), ((100, 0, 100), (150, 0, 50)));
+        let mass_action = with(Kinetics::MassAction(255.0));
+        assert_eq!((run(&mass_action, 100), run(&mass_action, 50)), ((122, 22, 78), (161, 11, 39)));
+        assert_eq!(run(&with(Kinetics::MassAction(1000.0)), 100), (100, 0, 100));
+    }
+
+    #[test]
+    fn resting_receptors_ignore_what_they_sense() {
+        let firings = |refractory| {
+            let receptor = Receptor {
+                refractory: refractory, .. Receptor::new(IoType::Digital, 1, 7, 50, 40, 10, false)
+            };
+            let mut creature = Creature::new();
+            creature.inject(1, 20);
+            (0 .. 12).filter(|_| {
+                receptor.step(&mut creature);
+                creature.get_locus(7) != 50
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(firings(0), (0 .. 12).collect::<Vec<_>>());
+        assert_eq!(firings(3), vec![0, 4, 8]);
+    }
+
+    #[test]
+    fn the_variants_table_describes_every_kind_of_reaction_in_order() {
+        let (a, b, c, d) = (chem(1, 1), chem(2, 1), chem(3, 1), chem(4, 1));
+        let kinds = [
+            ReactionType::Normal(a, b, c, d), ReactionType::Fusion(a, b, c),
+            ReactionType::Decay(a), ReactionType::Catalytic(a, b, c),
+            ReactionType::CatalyticBreakdown(a, b), ReactionType::Split(a, b, c),
+            ReactionType::Autocatalytic(a, b),
+        ];
+        assert_eq!(ReactionType::variants().len(), kinds.len());
+        for (info, kind) in ReactionType::variants().iter().zip(kinds.iter()) {
+            let json = encode(kind).unwrap();
+            assert!(json.starts_with(&format!("{{\"variant\":\"{}\"", info.name)), "{}", json);
+            assert_eq!(kind.inputs().len(), info.reactants + info.catalysts);
+            let reaction = Reaction::new(*kind, 0);
+            assert_eq!(reaction.reactants().len(), info.reactants);
+            assert_eq!(reaction.products().len(), info.products);
+        }
+    }
+
+    #[test]
+    fn capping_only_brings_chemicals_down() {
+        let mut body = body_with(&[(1, 200), (2, 40)]);
+        body.cap(1, 77);
+        body.cap(2, 77);
+        body.cap(3, 77);
+        assert_eq!((body.peek(1), body.peek(2), body.peek(3)), (77, 40, 0));
+    }
+
+    #[test]
+    fn emitters_only_change_their_own_chemical() {
+        let emitter = Emitter::new(IoType::Digital, 2, 0, 30, 0, 0, false, false);
+        for filled in [vec![], vec![(1, 40), (2, 50), (3, 60)]].iter() {
+            let mut creature = Creature::new();
+            for &(id, concnt) in filled.iter() {
+                creature.inject(id, concnt);
+            }
+            let before = creature.concentrations();
+            emitter.step(&mut creature, &StepContext::new());
+            let after = creature.concentrations();
+            assert_eq!(after[&2], before.get(&2).unwrap_or(&0) + 30);
+            assert!(after.iter().all(|(id, concnt)| {
+                *id == 2 || before.get(id).unwrap_or(&0) == concnt
+            }));
+            assert!(before.keys().all(|id| after.contains_key(id)));
+        }
+    }
+
+    #[test]
+    fn edge_receptors_report_rising_and_falling_crossings() {
+        let edge = |invert| Receptor {
+            edge: true, .. Receptor::new(IoType::Digital, 1, 7, 100, 40, 50, invert)
+        };
+        let outputs = |receptor: Receptor| {
+            let mut creature = Creature::new();
+            [30, 60, 70, 40, 20, 50, 51].iter().map(|&level| {
+                creature.drain(1, 255);
+                creature.inject(1, level);
+                receptor.step(&mut creature);
+                creature.get_locus(7)
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(outputs(edge(false)), vec![100, 140, 100, 60, 100, 100, 140]);
+        assert_eq!(outputs(edge(true)), vec![100, 60, 100, 140, 100, 100, 60]);
+        let receptor = edge(false);
+        assert_eq!(receptor.respond(40, 60, 0), (140, 0, true));
+        assert_eq!(receptor.respond(60, 40, 0), (60, 0, true));
+        assert_eq!(receptor.respond(60, 70, 0), (100, 0, false));
+    }
+
+    #[test]
+    fn reactants_and_products_follow_the_shape_of_each_kind() {
+        let (a, b, c, d) = (chem(1, 1), chem(2, 1), chem(3, 1), chem(4, 1));
+        let cases = vec![
+            (ReactionType::Normal(a, b, c, d), vec![1, 2], vec![3, 4]),
+            (ReactionType::Fusion(a, b, c), vec![1, 2], vec![3]),
+            (ReactionType::Decay(a), vec![1], vec![]),
+            (ReactionType::Catalytic(a, b, c), vec![2], vec![3]),
+            (ReactionType::CatalyticBreakdown(a, b), vec![2], vec![]),
+            (ReactionType::Split(a, b, c), vec![1], vec![2, 3]),
+        ];
+        let ids = |chems: Vec<&Chemical>| chems.iter().map(|c| c.id()).collect::<Vec<_>>();
+        for (kind, reactants, products) in cases {
+            let mut reaction = Reaction::new(kind, 0);
+            assert_eq!((ids(reaction.reactants()), ids(reaction.products())),
+                       (reactants.clone(), products.clone()));
+            for chem in reaction.reactants_mut() {
+                *chem = Chemical::with_concentration(chem.id() + 10, 5);
+            }
+            for chem in reaction.products_mut() {
+                *chem = Chemical::with_concentration(chem.id() + 20, 6);
+            }
+            let shifted = |list: &[Id], by| list.iter().map(|id| id + by).collect::<Vec<_>>();
+            assert_eq!((ids(reaction.reactants()), ids(reaction.products())),
+                       (shifted(&reactants, 10), shifted(&products, 20)));
+            assert!(reaction.reactants().iter().all(|c| c.concnt() == 5));
+            assert!(reaction.products().iter().all(|c| c.concnt() == 6));
+        }
+    }
+
+    #[test]
+    fn homeostats_pull_chemicals_toward_their_target_from_either_side() {
+        let levels = |homeostat: &Homeostat, start| {
+            let mut creature = Creature::new();
+            creature.inject(1, start);
+            (0 .. 8).map(|_| {
+                homeostat.step(&mut creature, &StepContext::new());
+                creature.chemo_body().peek(1)
+            }).collect::<Vec<_>>()
+        };
+        let homeostat = Homeostat::new(1, 100.0, 0.5);
+        assert_eq!(levels(&homeostat, 0), vec![50, 75, 88, 94, 97, 99, 100, 100]);
+        assert_eq!(levels(&homeostat, 200), vec![150, 125, 112, 106, 103, 101, 100, 100]);
+        let eager = Homeostat::new(1, 100.0, 3.0);
+        assert_eq!(levels(&eager, 20), vec![100; 8]);
+    }
+
+    #[test]
+    fn splits_use_up_their_reactant_and_make_both_products_in_proportion() {
+        let split = ReactionType::Split(chem(1, 1), chem(2, 1), chem(3, 2));
+        let reaction = Reaction {
+            kinetics: Kinetics::MassAction(127.5), .. Reaction::new(split, 0)
+        };
+        let mut creature = Creature::new();
+        creature.inject(1, 120);
+        let mut levels = Vec::new();
+        for _ in 0 .. 5 {
+            reaction.step(&mut creature, &StepContext::new());
+            let body = creature.chemo_body();
+            assert_eq!((body.peek(1) as u16 + body.peek(2) as u16, body.peek(3)),
+                       (120, body.peek(2) * 2));
+            levels.push(body.peek(1));
+        }
+        assert_eq!(levels, vec![60, 30, 15, 8, 4]);
+        let mut creature = Creature::new();
+        creature.inject(1, 50);
+        Reaction::new(split, 0).step(&mut creature, &StepContext::new());
+        assert_eq!(creature.concentrations()[&1], 0);
+        assert_eq!((creature.concentrations()[&2], creature.concentrations()[&3]), (50, 100));
+    }
+
+    #[test]
+    fn exchanges_conserve_the_total_of_both_chemicals() {
+        let exchange = Exchange::new(1, 2, 0.3);
+        for &(from, to) in [(200, 40), (200, 250), (3, 0)].iter() {
+            let mut creature = Creature::new();
+            creature.inject(1, from);
+            creature.inject(2, to);
+            for _ in 0 .. 50 {
+                exchange.step(&mut creature, &StepContext { dt: 0.7, .. StepContext::new() });
+                let body = creature.chemo_body();
+                assert_eq!(body.peek(1) as u16 + body.peek(2) as u16, from as u16 + to as u16);
+            }
+        }
+        let mut creature = Creature::new();
+        creature.inject(1, 200);
+        exchange.step(&mut creature, &StepContext::new());
+        assert_eq!((creature.concentrations()[&1], creature.concentrations()[&2]), (140, 60));
+    }
+
+    #[test]
+    fn relative_genes_work_in_fractions_of_their_group() {
+        let group = Some(vec![1, 2]);
+        let receptor = Receptor {
+            relative_to: group.clone(), .. Receptor::new(IoType::Digital, 1, 7, 0, 200, 100, false)
+        };
+        let emitter = Emitter {
+            relative_to: group, .. Emitter::new(IoType::Digital, 3, 0, 51, 0, 0, false, false)
+        };
+        let run = |other| {
+            let mut creature = Creature::new();
+            creature.inject(1, 50);
+            creature.inject(2, other);
+            receptor.step(&mut creature);
+            emitter.step(&mut creature, &StepContext::new());
+            let made = creature.chemo_body().peek(3);
+            (creature.get_locus(7), made)
+        };
+        assert_eq!(run(50), (200, 20));
+        assert_eq!(run(150), (0, 40));
+        assert_eq!(run(0), (200, 10));
+        let mut creature = Creature::new();
+        receptor.step(&mut creature);
+        assert_eq!(creature.get_locus(7), 0);
+    }
+
+    fn random_reactions(seed: usize, count: usize) -> Vec<Reaction> {
+        let mut rng: StdRng = SeedableRng::from_seed(&[seed][..]);
+        (0 .. count).map(|_| Reaction::new(rng.gen(), 1)).collect()
+    }
+
+    #[test]
+    fn buffered_deltas_match_the_map() {
+        let mut buffer = DeltaBuffer::new();
+        let mut runs: StdRng = SeedableRng::from_seed(&[6][..]);
+        // Reusing one buffer checks that clearing it leaves nothing behind from the last reaction.
+        for reaction in random_reactions(5, 500) {
+            let n = runs.gen_range(0, 20);
+            buffer.clear();
+            reaction.each_delta(n, |id, delta| buffer.add(id, delta));
+            let deltas = reaction.deltas(n);
+            // The map keeps chemicals a reaction run no times leaves alone, with a change of 0.
+            let changed = |(_, delta): &(Id, i16)| *delta != 0;
+            let collected = buffer.touched().iter().map(|&id| (id, buffer.get(id)));
+            assert_eq!(collected.filter(&changed).collect::<DeltaMap>(),
+                       deltas.iter().map(|(&id, &delta)| (id, delta)).filter(&changed).collect());
+            let start: Vec<_> = (0 .. 256).map(|id| (id as Id, runs.gen())).collect();
+            let mut dense = body_with(&start);
+            let mut mapped = body_with(&start);
+            buffer.apply_to(&mut dense);
+            mapped.apply(&deltas);
+            assert_eq!(dense.snapshot(), mapped.snapshot());
+        }
+    }
+
+    // Run with `cargo test -- --ignored --nocapture` to compare timings.
+    #[test]
+    #[ignore]
+    fn bench_delta_buffers_against_maps() {
+        let reactions = random_reactions(7, 1000);
+        let start = Instant::now();
+        let mut mapped = ChemoBody::new();
+        for _ in 0 .. 200 {
+            for reaction in reactions.iter() {
+                mapped.apply(&reaction.deltas(3));
+            }
+        }
+        let map = start.elapsed();
+        let start = Instant::now();
+        let mut dense = ChemoBody::new();
+        let mut buffer = DeltaBuffer::new();
+        for _ in 0 .. 200 {
+            for reaction in reactions.iter() {
+                buffer.clear();
+                reaction.each_delta(3, |id, delta| buffer.add(id, delta));
+                buffer.apply_to(&mut dense);
+            }
+        }
+        let buffered = start.elapsed();
+        assert_eq!(dense.snapshot(), mapped.snapshot());
+        println!("DeltaBuffer took {:?}, DeltaMap took {:?}.", buffered, map);
+    }
+
+    #[test]
+    fn autocatalytic_growth_speeds_up_then_levels_off() {
+        let reaction = Reaction {
+            kinetics: Kinetics::MassAction(400.0),
+            .. Reaction::new(ReactionType::Autocatalytic(chem(1, 1), chem(2, 1)), 0)
+        };
+        let mut creature = Creature::new();
+        creature.inject(1, 250);
+        creature.inject(2, 2);
+        let mut product = Vec::new();
+        for _ in 0 .. 9 {
+            reaction.step(&mut creature, &StepContext::new());
+            let body = creature.chemo_body();
+            assert_eq!(body.peek(1) as u16 + body.peek(2) as u16, 252);
+            product.push(body.peek(2));
+        }
+        assert_eq!(product, vec![4, 8, 16, 32, 64, 128, 225, 252, 252]);
+    }
+
+    #[test]
+    fn stochastic_reactions_average_the_deterministic_amount() {
+        let reaction = Reaction {
+            stochastic: true, kinetics: Kinetics::MassAction(51.0),
+            .. Reaction::new(ReactionType::Decay(chem(1, 1)), 0)
+        };
+        let body = body_with(&[(1, 200)]);
+        assert_eq!(reaction.extent(&body), 40);
+        let draws = |ctx: &StepContext| -> Vec<i16> {
+            (0 .. 1000).map(|_| -reaction.step_pure(0.0, &body, ctx).1[&1]).collect()
+        };
+        let first = draws(&StepContext::with_seed(7));
+        assert!(first == draws(&StepContext::with_seed(7)));
+        let mean = first.iter().map(|&n| n as f32).sum::<f32>() / first.len() as f32;
+        assert!((mean - 40.0).abs() < 1.0, "Mean of {} is too far from 40.", mean);
+    }
+
+    #[test]
+    fn stochastic_reactions_never_outrun_their_reactants() {
+        let reaction = Reaction {
+            stochastic: true,
+            .. Reaction::new(ReactionType::Fusion(chem(1, 1), chem(2, 1), chem(3, 1)), 0)
+        };
+        let body = body_with(&[(1, 9), (2, 200)]);
+        let ctx = StepContext::with_seed(3);
+        for _ in 0 .. 200 {
+            let (_, deltas) = reaction.step_pure(0.0, &body, &ctx);
+            assert!(deltas[&3] <= 9);
+            assert_eq!(deltas[&3], -deltas[&1]);
+        }
+    }
+
+    #[test]
+    fn steps_longer_than_the_rate_fire_for_each_rate_they_cover() {
+        let emitter = Emitter::new(IoType::Digital, 1, 1, 5, 0, 0, false, false);
+        let mut creature = Creature::new();
+        emitter.step(&mut creature, &StepContext { dt: 3.0, .. StepContext::new() });
+        assert_eq!(creature.chemo_body().peek(1), 15);
+    }
+
+    #[test]
+    fn delta_buffers_apply_like_delta_maps() {
+        let changes = [(1, 40), (2, -30), (1, -15), (3, 200), (3, 100), (2, 30), (4, -90)];
+        let mut map = DeltaMap::new();
+        let mut buffer = DeltaBuffer::new();
+        for &(id, delta) in changes.iter() {
+            *map.entry(id).or_insert(0) += delta;
+            buffer.add(id, delta);
+        }
+        let mut from_map = body_with(&[(1, 10), (2, 20), (4, 50)]);
+        let mut from_buffer = from_map.clone();
+        from_map.apply(&map);
+        buffer.apply_to(&mut from_buffer);
+        assert_eq!(from_map.snapshot(), from_buffer.snapshot());
+        assert_eq!(buffer.touched(), &[1, 2, 3, 4]);
+        buffer.clear();
+        assert!(buffer.touched().is_empty() && buffer.get(3) == 0);
+    }
+
+    #[test]
+    fn buffered_reactions_make_the_changes_they_simulate() {
+        let reaction = Reaction::new(ReactionType::Normal(chem(1, 2), chem(2, 1), chem(3, 1),
+                                                          chem(1, 1)), 0);
+        let mut creature = Creature::new();
+        creature.inject(1, 90);
+        creature.inject(2, 30);
+        let mut buffer = DeltaBuffer::new();
+        for _ in 0 .. 5 {
+            let mut expected = creature.chemo_body().clone();
+            let deltas = reaction.simulate_once(&expected);
+            expected.apply(&deltas);
+            reaction.step_buffered(&mut creature, &StepContext::new(), 255, &mut buffer);
+            assert_eq!(creature.chemo_body().snapshot(), expected.snapshot());
+        }
     }
 }
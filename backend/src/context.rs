@@ -0,0 +1,87 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use chem::{Buffer, Concentration, DeltaMap, EmitterGroup, Id};
+use creature::Creature;
+use rand::{SeedableRng, StdRng};
+
+/// Settings that apply to a whole simulation step rather than to any single gene.
+pub struct StepContext {
+    pub buffers: Vec<Buffer>,
+    /// How many ticks pass in one step. Emitters and reactions fire once `rate` ticks have passed
+    /// and put out the same amount each time, so a `dt` of 0.5 makes them fire half as often and
+    /// twice as many steps are needed to cover the same time. Time a step runs past a firing counts
+    /// towards the next one, and a step longer than a gene's rate fires it once for every time the
+    /// rate fits, so genes keep their rate whatever the `dt`.
+    pub dt: f32,
+    /// The source of randomness for stochastic genes. Without one, they behave deterministically.
+    pub rng: Option<RefCell<StdRng>>,
+    /// Snaps concentrations at the end of every step to this many levels above zero, for modelling
+    /// counts of molecules instead of amounts. See `ChemoBody::quantize`.
+    pub quantize: Option<u8>,
+    /// Carries this fraction (0-1) of every chemical's net change in a step over into the next
+    /// step, right after the genes run, so changes fade out over several steps rather than
+    /// stopping dead. This changes the dynamics a lot: a single pulse from an emitter goes on
+    /// raising its chemical for several steps after.
+    pub leak: Option<f32>,
+    /// The change `leak` is carrying into the next step. It belongs to whatever the context last
+    /// stepped, so a context with a leak must not be shared between creatures, or between genomes
+    /// stepping the same creature: give each its own context, or clear this when switching.
+    pub carried: RefCell<DeltaMap>,
+    /// The fraction (0-1) of each listed chemical lost every tick, applied after the genes and
+    /// before buffers, rounding down. It saves giving every chemical its own `Decay` gene, and adds
+    /// to any such gene rather than replacing it.
+    pub decay_rates: HashMap<Id, f32>,
+    /// The highest each listed chemical may reach. Going over is taken for a bug in the genome:
+    /// at the very end of every step, debug builds panic naming the chemical, and release builds
+    /// bring it back down to its cap. Other chemicals top out at 255 as usual.
+    pub caps: HashMap<Id, Concentration>,
+    /// Nudges every concentration by up to this much either way at the end of every step, before
+    /// quantizing, to model a noisy environment. It draws on `rng` and does nothing without one.
+    pub noise: Option<u8>,
+    /// Groups of emitters that share a gain budget. See `EmitterGroup`.
+    pub emitter_groups: Vec<EmitterGroup>,
+    /// Sizes every reaction that fires in a step against the concentrations at the start of the
+    /// step, and scales down reactions competing for a reactant in proportion to how much of it
+    /// they want whenever together they want more than there is. Without it, reactions earlier in
+    /// the genome get first claim on what they need.
+    pub share_reactants: bool,
+    /// Lets every gene see the changes the genes before it made earlier in the same step, which is
+    /// the default. Turning it off runs every gene against the concentrations the step started
+    /// with instead, and adds up their changes once they have all run, as though the genes acted
+    /// at the same moment. This changes the dynamics: a chain of reactions then moves one link
+    /// per step rather than running through in one. Loci are unaffected, so genes still see what
+    /// receptors earlier in the step wrote.
+    pub sequential: bool,
+}
+
+impl StepContext {
+    pub fn new() -> StepContext {
+        StepContext {
+            buffers: Vec::new(), dt: 1.0, rng: None, quantize: None, noise: None, leak: None,
+            carried: RefCell::new(DeltaMap::new()), decay_rates: HashMap::new(),
+            caps: HashMap::new(), emitter_groups: Vec::new(), share_reactants: false,
+            sequential: true
+        }
+    }
+
+    pub fn with_seed(seed: usize) -> StepContext {
+        let rng = SeedableRng::from_seed(&[seed][..]);
+        StepContext { rng: Some(RefCell::new(rng)), .. StepContext::new() }
+    }
+}
+
+/// The same as `StepContext::new`: one tick per step, genes running one after another, and no
+/// buffers, groups, leak, decay, caps, quantization, RNG, noise or sharing of reactants, which is
+/// how `Genome::step` runs.
+impl Default for StepContext {
+    fn default() -> StepContext {
+        StepContext::new()
+    }
+}
+
+/// Anything that can run as part of a genome's step. The built-in emitters, reactions and
+/// receptors implement it, and other crates can implement it to add their own kinds of gene via
+/// `Gene::Custom`.
+pub trait Steppable {
+    fn step(&self, creature: &mut Creature, ctx: &StepContext);
+}
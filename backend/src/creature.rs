@@ -1,10 +1,19 @@
 use std::cell::{Ref, RefCell, RefMut};
-use std::collections::HashMap;
-use chem::ChemoBody;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::io::prelude::*;
+use std::path::Path;
+use chem::{ChemoBody, Concentration, Id};
+use genome::Genome;
+use rustc_serialize::json::{decode, encode};
 
 pub type LocusId = u8;
 pub type LocusValue = u8;
 
+/// A creature's loci and chemistry. Its age lives in the ageing loci, so saving a creature and
+/// loading it again resumes it at the same life stage with the same concentrations.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
 pub struct Creature {
     loci: RefCell<HashMap<LocusId, LocusValue>>,
     chem: RefCell<ChemoBody>,
@@ -15,6 +24,32 @@ impl Creature {
         Creature { loci: RefCell::new(HashMap::new()), chem: RefCell::new(ChemoBody::new()) }
     }
 
+    /// A creature holding every chemical `genome` refers to at zero concentration, and nothing
+    /// else. The genome's initial state is not applied.
+    pub fn for_genome(genome: &Genome) -> Creature {
+        let creature = Creature::new();
+        let ids: Vec<Id> = genome.referenced_chemicals().into_iter().collect();
+        creature.ensure_chemicals(&ids);
+        creature
+    }
+
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Creature> {
+        let mut f = try!(File::open(path.as_ref()));
+        let mut data = String::new();
+        try!(f.read_to_string(&mut data));
+        decode(&data).map_err(|_|
+            Error::new(ErrorKind::InvalidInput, "Failed to decode creature.")
+        )
+    }
+
+    pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<()> {
+        let mut f = try!(File::create(path.as_ref()));
+        try!(f.write_all(try!(encode(self).map_err(|_|
+            Error::new(ErrorKind::InvalidInput, "Failed to encode creature.")
+        )).as_bytes()));
+        f.flush()
+    }
+
     pub fn get_locus(&self, id: LocusId) -> LocusValue {
         *self.loci.borrow_mut().entry(id).or_insert(0)
     }
@@ -31,6 +66,46 @@ impl Creature {
         self.chem.borrow_mut()
     }
 
+    /// The concentration of every chemical the creature has, keyed by id.
+    pub fn concentrations(&self) -> HashMap<Id, Concentration> {
+        self.chem.borrow().snapshot()
+    }
+
+    /// How far the creature's chemistry is from `target`, as the sum of the squared differences
+    /// in concentration. Chemicals missing from either side count as zero.
+    pub fn profile_distance(&self, target: &HashMap<Id, Concentration>) -> f32 {
+        let body = self.chem.borrow();
+        let ids: BTreeSet<Id> = body.snapshot().keys().chain(target.keys()).cloned().collect();
+        ids.into_iter().map(|id| {
+            let difference = body.peek(id) as f32 - *target.get(&id).unwrap_or(&0) as f32;
+            difference * difference
+        }).sum()
+    }
+
+    /// Adds a chemical from outside the genome, e.g. when the creature is fed. The concentration
+    /// saturates at 255.
+    pub fn inject(&self, id: Id, amount: Concentration) {
+        self.chem.borrow_mut().gain(id, amount);
+    }
+
+    /// Removes a chemical from outside the genome. The concentration bottoms out at zero.
+    pub fn drain(&self, id: Id, amount: Concentration) {
+        self.chem.borrow_mut().drain(id, amount);
+    }
+
+    /// Registers each of `ids` at zero concentration unless the creature already has it.
+    pub fn ensure_chemicals(&self, ids: &[Id]) {
+        let mut body = self.chem.borrow_mut();
+        for id in ids.iter() {
+            body.ensure(*id);
+        }
+    }
+
+    /// Sets every chemical the creature has to zero while keeping them registered.
+    pub fn zero(&self) {
+        self.chem.borrow_mut().zero();
+    }
+
     fn get(&self, id: Locus) -> LocusValue {
         *self.loci.borrow_mut().entry(id as u8).or_insert(0)
     }
@@ -76,7 +151,7 @@ enum Locus {
     Hunger           = 7,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Age {
     Baby,
     Child,
@@ -90,3 +165,96 @@ pub enum Age {
 pub enum Drive {
     Hunger
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use chem::{Chemical, Emitter, IoType, Reaction, ReactionType};
+    use genome::Gene;
+    use super::*;
+
+    #[test]
+    fn injecting_saturates_and_draining_bottoms_out() {
+        let creature = Creature::new();
+        creature.inject(1, 200);
+        creature.inject(1, 100);
+        assert_eq!(creature.chemo_body().peek(1), 255);
+        creature.drain(1, 55);
+        assert_eq!(creature.chemo_body().peek(1), 200);
+        creature.drain(1, 250);
+        assert_eq!(creature.chemo_body().peek(1), 0);
+    }
+
+    #[test]
+    fn saved_creatures_load_with_their_chemistry_and_age() {
+        let creature = Creature::new();
+        for locus in 4 .. 7 {
+            creature.set_locus(locus, 1);
+        }
+        creature.inject(1, 80);
+        creature.inject(200, 3);
+        let path = env::temp_dir().join(format!("life-sim-creature-{}.json", process::id()));
+        creature.save(&path).unwrap();
+        let loaded = Creature::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.concentrations(), creature.concentrations());
+        assert_eq!((loaded.age(), creature.age()), (Age::Youth, Age::Youth));
+    }
+
+    #[test]
+    fn ensuring_chemicals_only_adds_missing_ones_and_zeroing_keeps_them() {
+        let creature = Creature::new();
+        creature.inject(1, 40);
+        creature.ensure_chemicals(&[1, 2, 3]);
+        let ensured: HashMap<Id, Concentration> =
+            vec![(1, 40), (2, 0), (3, 0)].into_iter().collect();
+        assert_eq!(creature.concentrations(), ensured);
+        creature.inject(3, 9);
+        creature.zero();
+        let zeroed: HashMap<Id, Concentration> =
+            vec![(1, 0), (2, 0), (3, 0)].into_iter().collect();
+        assert_eq!(creature.concentrations(), zeroed);
+    }
+
+    #[test]
+    fn concentrations_match_the_body() {
+        let creature = Creature::new();
+        creature.inject(3, 30);
+        creature.inject(250, 7);
+        creature.ensure_chemicals(&[9]);
+        let concentrations = creature.concentrations();
+        assert_eq!(concentrations.len(), 3);
+        let body = creature.chemo_body();
+        assert!(concentrations.iter().all(|(&id, &concnt)| body.peek(id) == concnt));
+        assert_eq!((concentrations[&3], concentrations[&250], concentrations[&9]), (30, 7, 0));
+    }
+
+    #[test]
+    fn profile_distance_sums_squared_differences_over_both_sides() {
+        let creature = Creature::new();
+        creature.inject(1, 10);
+        creature.inject(2, 100);
+        creature.inject(3, 7);
+        let target: HashMap<Id, Concentration> =
+            vec![(1, 13), (2, 96), (4, 5)].into_iter().collect();
+        assert_eq!(creature.profile_distance(&target), (9 + 16 + 49 + 25) as f32);
+        assert_eq!(creature.profile_distance(&creature.concentrations()), 0.0);
+        assert_eq!(Creature::new().profile_distance(&HashMap::new()), 0.0);
+    }
+
+    #[test]
+    fn creatures_for_a_genome_hold_exactly_its_chemicals_at_zero() {
+        let genome = Genome::from_genes(vec![
+            Gene::InitialState(Chemical::with_concentration(1, 100)),
+            Gene::Emitter(Emitter::new(IoType::Digital, 2, 3, 7, 0, 0, false, false)),
+            Gene::Reaction(Reaction::new(ReactionType::Fusion(
+                Chemical::with_concentration(1, 1), Chemical::with_concentration(2, 1),
+                Chemical::with_concentration(9, 1)), 4)),
+        ]);
+        let creature = Creature::for_genome(&genome);
+        let expected: HashMap<Id, Concentration> =
+            vec![(1, 0), (2, 0), (9, 0)].into_iter().collect();
+        assert_eq!(creature.concentrations(), expected);
+        assert!(Creature::for_genome(&Genome::from_genes(vec![])).concentrations().is_empty());
+    }
+}
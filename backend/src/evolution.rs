@@ -0,0 +1,403 @@
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::iter::repeat;
+use std::time::{Duration, Instant};
+use chem::Id;
+use creature::{Age, Creature};
+use genome::{Gene, Genome};
+use rand::{Rng, SeedableRng, StdRng};
+use rustc_serialize::json::encode;
+
+/// Settings that shape how genomes change from one generation to the next.
+pub struct EvolveConfig {
+    /// The largest a genome may grow through mutation. Genomes at the cap only have genes tweaked
+    /// or removed.
+    pub max_genes: Option<usize>,
+    /// Turns on fitness sharing with the given radius. See `shared_fitness`.
+    pub sharing: Option<f32>,
+    /// How long `evolve` may spend. Once it runs out, no further candidates are tried and the best
+    /// of those tried so far wins. At least one candidate is always tried.
+    pub time_budget: Option<Duration>,
+    /// Called at the end of every `evolve` with the generation's number and how its candidates
+    /// did. Generations are numbered from 0 across every call made with this config.
+    pub on_generation: Option<RefCell<GenerationHook>>,
+    /// The lowest and highest chemical ids that mutations may use. Genes a mutation adds or
+    /// changes have all their ids brought into this range.
+    pub ids: Option<(Id, Id)>,
+    /// How likely mutations are to add each kind of gene.
+    pub kind_weights: KindWeights,
+    /// The indices of genes that mutations must leave exactly as they are, where they are. Genes
+    /// are only ever added after them, and a gene is not removed if that would move one of them.
+    pub frozen: HashSet<usize>,
+    generation: Cell<usize>,
+}
+
+impl EvolveConfig {
+    pub fn new() -> EvolveConfig {
+        EvolveConfig {
+            max_genes: None, sharing: None, time_budget: None, on_generation: None, ids: None,
+            kind_weights: KindWeights::default(), frozen: HashSet::new(), generation: Cell::new(0)
+        }
+    }
+}
+
+/// The same as `EvolveConfig::new`: genomes may grow without limit, fitness is not shared, there
+/// is no time limit, nothing is called after each generation, any chemical id may be used, every
+/// kind of gene is equally likely to be added and no genes are frozen.
+impl Default for EvolveConfig {
+    fn default() -> EvolveConfig {
+        EvolveConfig::new()
+    }
+}
+
+/// How often `random_gene` picks each kind of gene, relative to the others. Only the kinds a
+/// random gene can be are listed. If every weight is 0, each kind is picked equally often.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct KindWeights {
+    pub initial_state: u32,
+    pub emitter: u32,
+    pub reaction: u32,
+    pub receptor: u32,
+}
+
+/// Every kind is equally likely.
+impl Default for KindWeights {
+    fn default() -> KindWeights {
+        KindWeights { initial_state: 1, emitter: 1, reaction: 1, receptor: 1 }
+    }
+}
+
+/// What `EvolveConfig::on_generation` is given after each generation.
+pub type GenerationHook = Box<FnMut(usize, &GenerationStats) + Send>;
+
+/// How a generation's candidates did. Fitnesses are as the fitness function gave them, before any
+/// sharing, and diversity is the mean `Genome::distance` between two different candidates (0 when
+/// there is only one).
+#[derive(Copy, Clone, Debug)]
+pub struct GenerationStats {
+    pub best: u32,
+    pub mean: f32,
+    pub worst: u32,
+    pub diversity: f32,
+}
+
+impl GenerationStats {
+    fn of(population: &[Fitness]) -> GenerationStats {
+        let total = population.iter().map(|fit| fit.0 as f32).sum::<f32>();
+        let mut distances = Vec::new();
+        for (i, a) in population.iter().enumerate() {
+            distances.extend(population[i + 1 ..].iter().map(|b| Genome::distance(&a.1, &b.1)));
+        }
+        GenerationStats {
+            best: population.iter().map(|fit| fit.0).min().unwrap_or(0),
+            mean: total / population.len().max(1) as f32,
+            worst: population.iter().map(|fit| fit.0).max().unwrap_or(0),
+            diversity: distances.iter().sum::<f32>() / distances.len().max(1) as f32,
+        }
+    }
+}
+
+/// Mutates `base` into `trials` candidates, runs each for up to `cap` ticks or until it is a
+/// `Baby`, and returns the fittest. Lower fitness is better.
+pub fn evolve<F, R>(base: Genome, trials: usize, cap: u32, config: &EvolveConfig, rng: &mut R,
+                    fitness: F) -> Fitness
+where F: Fn(u32, Genome) -> Fitness, R: Rng {
+    evolve_logged(base, trials, cap, config, rng, fitness, &mut EvolutionLog::new())
+}
+
+/// Like `evolve`, but also records the mutations that produced the winner in `log`.
+pub fn evolve_logged<F, R>(base: Genome, trials: usize, cap: u32, config: &EvolveConfig,
+                           rng: &mut R, fitness: F, log: &mut EvolutionLog) -> Fitness
+where F: Fn(u32, Genome) -> Fitness, R: Rng {
+    let start = Instant::now();
+    let in_time = |budget: Duration| start.elapsed() < budget;
+    let candidates = repeat(base).take(trials).enumerate().take_while(|&(i, _)| {
+        i == 0 || config.time_budget.map_or(true, &in_time)
+    }).map(|(_, mut genome)| {
+        let events = genome.mutate_n(3, rng, config);
+        (trial(genome, cap, &fitness), events)
+    });
+    let (mut population, mut events): (Vec<_>, Vec<_>) = candidates.unzip();
+    let best = match config.sharing {
+        None => (0 .. population.len()).min_by(|&i, &j| population[i].cmp(&population[j])),
+        Some(radius) => {
+            let shared = shared_fitness(&population, radius);
+            (0 .. population.len()).min_by(|&i, &j| {
+                shared[i].partial_cmp(&shared[j]).unwrap_or(Ordering::Equal)
+            })
+        }
+    }.unwrap();
+    if let Some(ref hook) = config.on_generation {
+        let generation = config.generation.get();
+        config.generation.set(generation + 1);
+        (&mut **hook.borrow_mut())(generation, &GenerationStats::of(&population));
+    }
+    let fit = population.swap_remove(best);
+    debug!("Generation of {} candidates done, best fitness {}.", population.len() + 1, fit.0);
+    log.record(events.swap_remove(best));
+    fit
+}
+
+/// Runs a small fixed evolution from `seed` and fingerprints the genome it ends up with, so that
+/// builds can be checked for determinism by comparing fingerprints. The RNG behind it works in
+/// machine words, so fingerprints only match between platforms with the same word size. On 64-bit
+/// platforms `determinism_check(42)` gives `0x6f8b8e47d3d5311e`; anything that changes how genomes
+/// evolve or are saved changes it too.
+pub fn determinism_check(seed: u64) -> u64 {
+    let mut rng: StdRng = SeedableRng::from_seed(&[seed as usize, (seed >> 32) as usize][..]);
+    let config = EvolveConfig::new();
+    let mut fit = Fitness(0, Genome::new());
+    for _ in 0 .. 5 {
+        fit = evolve(fit.1, 20, 200, &config, &mut rng, |ticks, genome| Fitness(ticks, genome));
+    }
+    // FNV-1a, which unlike the standard library's hasher is fixed.
+    encode(&fit.1).unwrap_or_default().bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Scores copies of `genome` from `starts` (at least one) random starting chemistries and keeps
+/// the best score, so that an unlucky start does not sink a good genome. Each start injects a few
+/// random chemicals before the genome's initial state is applied, then runs like a trial in
+/// `evolve`. The same RNG state always gives the same result.
+pub fn best_of<F, R>(genome: &Genome, starts: usize, cap: u32, fitness: &F, rng: &mut R) -> Fitness
+where F: Fn(u32, Genome) -> Fitness, R: Rng {
+    (0 .. starts.max(1)).map(|_| {
+        let creature = Creature::new();
+        for _ in 0 .. 8 {
+            creature.inject(rng.gen(), rng.gen());
+        }
+        trial_from(creature, genome.clone(), cap, fitness)
+    }).min().unwrap()
+}
+
+fn trial<F>(genome: Genome, cap: u32, fitness: &F) -> Fitness where F: Fn(u32, Genome) -> Fitness {
+    trial_from(Creature::new(), genome, cap, fitness)
+}
+
+fn trial_from<F>(mut creature: Creature, genome: Genome, cap: u32, fitness: &F) -> Fitness
+where F: Fn(u32, Genome) -> Fitness {
+    genome.init(&mut creature);
+    for t in 0 .. cap {
+        genome.step(&mut creature);
+        if creature.age() == Age::Baby { return fitness(t, genome) }
+    }
+    fitness(cap, genome)
+}
+
+/// Scales each genome's fitness by the size of its niche, the number of genomes in the population
+/// (itself included) within `radius` of it by `Genome::distance`. Since lower fitness is better,
+/// crowded genomes lose out to equally fit ones that explore somewhere new.
+pub fn shared_fitness(population: &[Fitness], radius: f32) -> Vec<f32> {
+    population.iter().map(|a| {
+        let niche = population.iter().filter(|b| Genome::distance(&a.1, &b.1) <= radius).count();
+        a.0 as f32 * niche as f32
+    }).collect()
+}
+
+pub struct Fitness(pub u32, pub Genome);
+
+impl PartialEq for Fitness {
+    fn eq(&self, other: &Fitness) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Fitness {}
+
+impl PartialOrd for Fitness {
+    fn partial_cmp(&self, other: &Fitness) -> Option<Ordering> {
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+impl Ord for Fitness {
+    fn cmp(&self, other: &Fitness) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+/// One change made by `Genome::mutate_once`. A gene added at the end has no `before`, a removed
+/// gene (whose slot the last gene then moves into) has no `after`, and a tweaked gene has both.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct MutationEvent {
+    pub index: usize,
+    pub before: Option<Gene>,
+    pub after: Option<Gene>,
+}
+
+/// The mutations behind every generation's winner in an evolution run. Replaying them onto the
+/// starting genome rebuilds the final genes exactly (tick counters aside, since those moved while
+/// the winner was scored) with no RNG involved, so a log stays valid even when the code that draws
+/// random numbers changes.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct EvolutionLog {
+    pub generations: Vec<Vec<MutationEvent>>,
+}
+
+impl EvolutionLog {
+    pub fn new() -> EvolutionLog {
+        EvolutionLog { generations: Vec::new() }
+    }
+
+    pub fn record(&mut self, events: Vec<MutationEvent>) {
+        self.generations.push(events);
+    }
+
+    pub fn replay(&self, mut base: Genome) -> Genome {
+        for event in self.generations.iter().flat_map(|events| events.iter()) {
+            base.apply_mutation(event);
+        }
+        base
+    }
+}
+
+impl Default for EvolutionLog {
+    fn default() -> EvolutionLog {
+        EvolutionLog::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use chem::{Chemical, Emitter, IoType};
+    use rand::{SeedableRng, StdRng};
+    use super::*;
+
+    fn rng(seed: usize) -> StdRng {
+        SeedableRng::from_seed(&[seed][..])
+    }
+
+    #[test]
+    fn sharing_penalises_a_crowded_niche() {
+        let crowd = Genome::from_genes(vec![
+            Gene::InitialState(Chemical::with_concentration(1, 10)),
+        ]);
+        let loner = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 2, 0, 5, 0, 0, false, false)),
+            Gene::Emitter(Emitter::new(IoType::Analogue, 3, 4, 5, 0, 0, false, false)),
+        ]);
+        let population = vec![Fitness(10, crowd.clone()), Fitness(10, crowd.clone()),
+                              Fitness(10, crowd), Fitness(10, loner)];
+        assert_eq!(shared_fitness(&population, 0.5), vec![30.0, 30.0, 30.0, 10.0]);
+    }
+
+    #[test]
+    fn replaying_the_log_rebuilds_the_winner() {
+        let base = Genome::from_genes(vec![
+            Gene::InitialState(Chemical::with_concentration(1, 10)),
+        ]);
+        let (config, mut rng, mut log) = (EvolveConfig::new(), rng(6), EvolutionLog::new());
+        let mut fit = Fitness(0, base.clone());
+        for _ in 0 .. 4 {
+            fit = evolve_logged(fit.1, 5, 20, &config, &mut rng, |ticks, genome| {
+                Fitness(ticks, genome)
+            }, &mut log);
+        }
+        assert_eq!(log.generations.len(), 4);
+        assert!(log.replay(base).approx_eq(&fit.1, 0.0));
+    }
+
+    #[test]
+    fn the_determinism_fingerprint_is_the_same_on_every_run() {
+        let fingerprint = determinism_check(42);
+        for _ in 0 .. 3 {
+            assert_eq!(determinism_check(42), fingerprint);
+        }
+        assert_eq!(thread::spawn(|| determinism_check(42)).join().unwrap(), fingerprint);
+        assert!(determinism_check(43) != fingerprint);
+        if cfg!(target_pointer_width = "64") {
+            assert_eq!(fingerprint, 0x6f8b8e47d3d5311e);
+        }
+    }
+
+    #[test]
+    fn evolution_stops_once_its_time_budget_is_spent() {
+        let base = Genome::from_genes(vec![
+            Gene::InitialState(Chemical::with_concentration(1, 10)),
+        ]);
+        let tried = Cell::new(0);
+        let counting = |ticks, genome| {
+            tried.set(tried.get() + 1);
+            Fitness(ticks, genome)
+        };
+        let mut config = EvolveConfig::new();
+        config.time_budget = Some(Duration::from_millis(0));
+        evolve(base.clone(), 1000, 50, &config, &mut rng(2), &counting);
+        assert_eq!(tried.get(), 1);
+        tried.set(0);
+        config.time_budget = Some(Duration::from_millis(50));
+        let start = Instant::now();
+        let fit = evolve(base, 1000000, 50, &config, &mut rng(2), &counting);
+        assert!(start.elapsed() < Duration::from_secs(5), "Took {:?}.", start.elapsed());
+        assert!(tried.get() >= 1 && tried.get() < 1000000);
+        assert!(fit.1.validate().is_empty());
+    }
+
+    #[test]
+    fn best_of_is_reproducible_for_a_seed() {
+        let genome = Genome::from_genes(vec![
+            Gene::InitialState(Chemical::with_concentration(1, 10)),
+            Gene::Emitter(Emitter::new(IoType::Analogue, 2, 3, 200, 0, 0, false, false)),
+        ]);
+        let calls = Cell::new(0);
+        let fitness = |ticks, genome| {
+            calls.set(calls.get() + 1);
+            Fitness(ticks, genome)
+        };
+        let (mut first, mut second) = (rng(11), rng(11));
+        for starts in 0 .. 4 {
+            let a = best_of(&genome, starts, 30, &fitness, &mut first);
+            let b = best_of(&genome, starts, 30, &fitness, &mut second);
+            assert!(a.0 == b.0 && a.1.approx_eq(&b.1, 0.0));
+        }
+        assert_eq!(calls.get(), 2 * (1 + 1 + 2 + 3));
+        assert_eq!(first.gen::<u64>(), second.gen::<u64>());
+    }
+
+    #[test]
+    fn the_hook_is_called_once_for_every_generation() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut config = EvolveConfig::new();
+        let record = seen.clone();
+        let hook = move |generation: usize, stats: &GenerationStats| {
+            record.lock().unwrap().push((generation, *stats));
+        };
+        config.on_generation = Some(RefCell::new(Box::new(hook)));
+        let mut fit = Fitness(0, Genome::from_genes(vec![
+            Gene::InitialState(Chemical::with_concentration(1, 10)),
+        ]));
+        let mut rng = rng(4);
+        for _ in 0 .. 5 {
+            fit = evolve(fit.1, 6, 20, &config, &mut rng, |ticks, genome| Fitness(ticks, genome));
+        }
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.iter().map(|&(generation, _)| generation).collect::<Vec<_>>(),
+                   vec![0, 1, 2, 3, 4]);
+        for &(_, stats) in seen.iter() {
+            assert!(stats.best as f32 <= stats.mean && stats.mean <= stats.worst as f32);
+            assert!(stats.worst <= 20 && stats.diversity >= 0.0);
+        }
+    }
+
+    #[test]
+    fn frozen_genes_survive_evolution() {
+        let base = Genome::from_genes(vec![
+            Gene::InitialState(Chemical::with_concentration(1, 10)),
+            Gene::Emitter(Emitter::new(IoType::Digital, 2, 0, 5, 0, 0, false, false)),
+            Gene::Emitter(Emitter::new(IoType::Analogue, 3, 4, 5, 0, 0, false, false)),
+        ]);
+        let mut config = EvolveConfig::new();
+        config.frozen = vec![0, 1].into_iter().collect();
+        let (mut rng, mut fit) = (rng(9), Fitness(0, base.clone()));
+        for _ in 0 .. 20 {
+            fit = evolve(fit.1, 5, 20, &config, &mut rng, |ticks, genome| Fitness(ticks, genome));
+            let kept = Genome::from_genes(fit.1.genes()[.. 2].to_vec());
+            assert!(kept.approx_eq(&Genome::from_genes(base.genes()[.. 2].to_vec()), 0.0));
+        }
+    }
+}
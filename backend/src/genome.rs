@@ -1,11 +1,23 @@
-use std::fs::File;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
 use std::io::{Error, ErrorKind, Result};
 use std::io::prelude::*;
+use std::iter::FromIterator;
+use std::mem::replace;
 use std::path::Path;
-use chem::{Chemical, Emitter, Reaction, Receptor};
-use creature::Creature;
+use std::result::Result as StdResult;
+use std::sync::{Arc, Once, RwLock};
+use std::time::SystemTime;
+use chem::{Chemical, Concentration, DeltaBuffer, DeltaMap, Emitter, Exchange, Homeostat, Id,
+           IoType, Kinetics, Reaction, ReactionGroup, ReactionType, Receptor, ReceptorType};
+use context::{StepContext, Steppable};
+use creature::{Creature, LocusValue};
+use evolution::{EvolveConfig, KindWeights, MutationEvent};
 use rand::{thread_rng, Rand, Rng};
-use rustc_serialize::json::{decode, encode};
+use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
+use rustc_serialize::json::{decode, encode, DecoderError};
 
 #[derive(Clone, RustcEncodable, RustcDecodable)]
 pub enum Gene {
@@ -14,19 +26,177 @@ pub enum Gene {
     Reaction(Reaction),
     Receptor(Receptor),
     Brain(usize, usize, Vec<f32>),
+    ReactionGroup(ReactionGroup),
+    Custom(CustomGene),
+    Homeostat(Homeostat),
+    Exchange(Exchange),
 }
 
 impl Rand for Gene {
     fn rand<R: Rng>(rng: &mut R) -> Gene {
-        match rng.gen_range(0, 4) {
-            1 => Gene::InitialState(rng.gen()),
-            2 => Gene::Emitter(rng.gen()),
-            3 => Gene::Reaction(rng.gen()),
-            _ => Gene::Receptor(rng.gen()),
+        random_gene(rng, (0, 255), &KindWeights::default())
+    }
+}
+
+impl Gene {
+    /// What the gene does during a step, if it does anything.
+    pub fn as_steppable(&self) -> Option<&Steppable> {
+        match *self {
+            Gene::Emitter(ref e) => Some(e),
+            Gene::Reaction(ref r) => Some(r),
+            Gene::Receptor(ref r) => Some(r),
+            Gene::ReactionGroup(ref g) => Some(g),
+            Gene::Custom(ref c) => Some(&*c.behaviour),
+            Gene::Homeostat(ref h) => Some(h),
+            Gene::Exchange(ref x) => Some(x),
+            _ => None
         }
     }
+
+    /// Steps a copy of the gene on its own against a creature holding `concentrations`, with a
+    /// default `StepContext`, and gives how much it changed each chemical. The copy's tick counter
+    /// starts where the gene's is, so a gene that would not fire in the next step of its genome
+    /// changes nothing here either. Genes that only write to loci, like receptors, never change
+    /// anything.
+    pub fn step_isolated(&self, concentrations: &HashMap<Id, Concentration>) -> DeltaMap {
+        let mut creature = creature_with(concentrations);
+        step_gene(&self.clone(), &mut creature, &StepContext::new());
+        creature.concentrations().into_iter().filter_map(|(id, concnt)| {
+            let old = *concentrations.get(&id).unwrap_or(&0);
+            if concnt != old { Some((id, concnt as i16 - old as i16)) } else { None }
+        }).collect()
+    }
+
+    /// Writes the gene out on its own, e.g. to share it between genomes.
+    pub fn to_json(&self) -> Result<String> {
+        encode(self).map_err(|_| Error::new(ErrorKind::InvalidInput, "Failed to encode gene."))
+    }
+
+    pub fn from_json(json: &str) -> Result<Gene> {
+        decode(json).map_err(|_| Error::new(ErrorKind::InvalidInput, "Failed to decode gene."))
+    }
+
+    /// A copy of the gene with every chemical id passed through `f`.
+    pub fn map_chemicals<F: Fn(Id) -> Id>(&self, f: F) -> Gene {
+        match *self {
+            Gene::InitialState(ref c) =>
+                Gene::InitialState(Chemical::with_concentration(f(c.id()), c.concnt())),
+            Gene::Emitter(ref e) =>
+                Gene::Emitter(Emitter {
+                    chemical: f(e.chemical), relative_to: map_group(&e.relative_to, &f),
+                    .. e.clone()
+                }),
+            Gene::Reaction(ref r) => Gene::Reaction(Reaction {
+                kind: r.kind.map_chemicals(&f), .. r.clone()
+            }),
+            Gene::Receptor(ref r) => Gene::Receptor(Receptor {
+                sense: r.sense.map_chemicals(&f), chemical: f(r.chemical),
+                relative_to: map_group(&r.relative_to, &f), .. r.clone()
+            }),
+            Gene::ReactionGroup(ref g) => Gene::ReactionGroup(ReactionGroup {
+                kinds: g.kinds.iter().map(|kind| kind.map_chemicals(&f)).collect(), .. g.clone()
+            }),
+            Gene::Homeostat(ref h) =>
+                Gene::Homeostat(Homeostat { chemical: f(h.chemical), .. h.clone() }),
+            Gene::Exchange(ref x) => Gene::Exchange(Exchange::new(f(x.from), f(x.to), x.rate)),
+            ref gene => gene.clone(),
+        }
+    }
+}
+
+/// Makes a random gene of a kind picked according to `weights`, whose chemical ids all lie between
+/// `ids.0` and `ids.1` inclusive, in either order.
+pub fn random_gene<R: Rng>(rng: &mut R, ids: (Id, Id), weights: &KindWeights) -> Gene {
+    let mut cumulative = [weights.initial_state, weights.emitter, weights.reaction,
+                          weights.receptor];
+    if cumulative.iter().all(|&w| w == 0) {
+        cumulative = [1; 4];
+    }
+    for i in 1 .. cumulative.len() {
+        cumulative[i] += cumulative[i - 1];
+    }
+    let pick = rng.gen_range(0, cumulative[3]);
+    let gene = match cumulative.iter().position(|&c| pick < c) {
+        Some(0) => Gene::InitialState(rng.gen()),
+        Some(1) => Gene::Emitter(rng.gen()),
+        Some(2) => Gene::Reaction(rng.gen()),
+        _ => Gene::Receptor(rng.gen()),
+    };
+    in_range(&gene, ids)
+}
+
+/// Builds the behaviour of a custom gene from the parameters it was saved with, or gives `None` if
+/// they do not make sense.
+pub type GeneConstructor = fn(&str) -> Option<Arc<Steppable + Send + Sync>>;
+
+type Registry = RwLock<BTreeMap<String, GeneConstructor>>;
+
+static REGISTRY_INIT: Once = Once::new();
+static mut REGISTRY: *const Registry = 0 as *const Registry;
+
+/// The custom gene registry, made on first use. `RwLock::new` cannot initialise a static on the
+/// compilers this crate supports, so the registry is boxed and leaked once instead.
+fn registry() -> &'static Registry {
+    unsafe {
+        REGISTRY_INIT.call_once(|| {
+            REGISTRY = Box::into_raw(Box::new(RwLock::new(BTreeMap::new())));
+        });
+        &*REGISTRY
+    }
+}
+
+/// Registers a kind of custom gene under `tag`, replacing any constructor already registered with
+/// that tag. Genes are saved as their tag and parameters, and loading one calls the constructor
+/// registered for its tag, so every kind a genome uses must be registered before it is loaded.
+/// Registrations are shared by every thread, so registering once at startup is enough.
+pub fn register_gene(tag: &str, constructor: GeneConstructor) {
+    registry().write().unwrap().insert(tag.to_owned(), constructor);
+}
+
+/// A gene whose behaviour is supplied from outside the crate through `register_gene`. Only the tag
+/// and parameters are saved, so any state the behaviour keeps between steps starts afresh on load.
+#[derive(Clone)]
+pub struct CustomGene {
+    pub tag: String,
+    pub params: String,
+    pub behaviour: Arc<Steppable + Send + Sync>,
+}
+
+impl CustomGene {
+    /// Builds a gene through the constructor registered for `tag`. Gives `None` if nothing is
+    /// registered under `tag` or the constructor rejects `params`.
+    pub fn new(tag: &str, params: &str) -> Option<CustomGene> {
+        let constructor = registry().read().unwrap().get(tag).cloned();
+        constructor.and_then(|constructor| constructor(params)).map(|behaviour| CustomGene {
+            tag: tag.to_owned(), params: params.to_owned(), behaviour: behaviour
+        })
+    }
+}
+
+impl Encodable for CustomGene {
+    fn encode<S: Encoder>(&self, s: &mut S) -> StdResult<(), S::Error> {
+        s.emit_struct("CustomGene", 2, |s| {
+            try!(s.emit_struct_field("tag", 0, |s| self.tag.encode(s)));
+            s.emit_struct_field("params", 1, |s| self.params.encode(s))
+        })
+    }
 }
 
+impl Decodable for CustomGene {
+    fn decode<D: Decoder>(d: &mut D) -> StdResult<CustomGene, D::Error> {
+        d.read_struct("CustomGene", 2, |d| {
+            let tag: String = try!(d.read_struct_field("tag", 0, |d| Decodable::decode(d)));
+            let params: String = try!(d.read_struct_field("params", 1, |d| Decodable::decode(d)));
+            CustomGene::new(&tag, &params).ok_or_else(|| {
+                d.error(&format!("No custom gene registered as \"{}\" accepts its parameters.",
+                                 tag))
+            })
+        })
+    }
+}
+
+/// Genes keep their tick counters in cells, so a genome can be sent to another thread but not
+/// shared between threads. To step one genome on several threads, give each thread its own clone.
 #[derive(Clone, RustcEncodable, RustcDecodable)]
 pub struct Genome {
     genes: Vec<Gene>
@@ -41,15 +211,76 @@ impl Genome {
         Genome { genes: genes }
     }
 
-    pub fn mutate(mut self) -> Genome {
-        let mut rng = thread_rng();
-        let val = rng.gen_range(0, self.genes.len() + 1);
+    /// The genome's genes, in order.
+    pub fn genes(&self) -> &[Gene] {
+        &self.genes
+    }
+
+    /// The genome's emitters, in genome order.
+    pub fn emitters(&self) -> Vec<&Emitter> {
+        self.genes.iter().filter_map(|gene| match *gene {
+            Gene::Emitter(ref e) => Some(e),
+            _ => None
+        }).collect()
+    }
+
+    /// The genome's reactions, in genome order. Reaction groups are left out.
+    pub fn reactions(&self) -> Vec<&Reaction> {
+        self.genes.iter().filter_map(|gene| match *gene {
+            Gene::Reaction(ref r) => Some(r),
+            _ => None
+        }).collect()
+    }
+
+    /// The genome's receptors, in genome order.
+    pub fn receptors(&self) -> Vec<&Receptor> {
+        self.genes.iter().filter_map(|gene| match *gene {
+            Gene::Receptor(ref r) => Some(r),
+            _ => None
+        }).collect()
+    }
+
+    pub fn mutate(self) -> Genome {
+        self.mutate_with(&mut thread_rng(), &EvolveConfig::new())
+    }
+
+    pub fn mutate_with<R: Rng>(mut self, rng: &mut R, config: &EvolveConfig) -> Genome {
+        self.mutate_once(rng, config);
+        self
+    }
+
+    /// Applies `n` mutations in place, one after another, and reports what each changed in order.
+    /// Mutations that find nothing they are allowed to do are left out of the report.
+    pub fn mutate_n<R: Rng>(&mut self, n: usize, rng: &mut R, config: &EvolveConfig)
+        -> Vec<MutationEvent> {
+        (0 .. n).filter_map(|_| self.mutate_once(rng, config)).collect()
+    }
+
+    /// Applies a single mutation in place and reports what it changed, or `None` if there was
+    /// nothing it was allowed to do. Brains have one of their weights nudged, so a brain without
+    /// weights is left as it is.
+    pub fn mutate_once<R: Rng>(&mut self, rng: &mut R, config: &EvolveConfig)
+        -> Option<MutationEvent> {
+        let full = config.max_genes.map_or(false, |max| self.genes.len() >= max);
+        let open: Vec<usize> = (0 .. self.genes.len()).filter(|i| {
+            !config.frozen.contains(i)
+        }).collect();
+        let choices = if full { open.len() } else { open.len() + 1 };
+        if choices == 0 { return None }
+        let val = *open.get(rng.gen_range(0, choices)).unwrap_or(&self.genes.len());
+        let last = self.genes.len().saturating_sub(1);
+        let removable = val == last || !config.frozen.contains(&last);
         if val == self.genes.len() {
-            self.genes.push(rng.gen());
-        } else if rng.gen_weighted_bool(self.genes.len() as u32) {
-            self.genes.swap_remove(val);
+            let gene = random_gene(rng, config.ids.unwrap_or((0, 255)), &config.kind_weights);
+            self.genes.push(gene.clone());
+            debug!("Mutation added gene {}.", val);
+            Some(MutationEvent { index: val, before: None, after: Some(gene) })
+        } else if rng.gen_weighted_bool(self.genes.len() as u32) && removable {
+            let gene = self.genes.swap_remove(val);
+            debug!("Mutation removed gene {}.", val);
+            Some(MutationEvent { index: val, before: Some(gene), after: None })
         } else {
-            self.genes[val] = match self.genes[val] {
+            let gene = match self.genes[val] {
                 Gene::InitialState(ref ch) => if rng.gen() {
                     Gene::InitialState(Chemical::with_concentration(ch.id(), rng.gen()))
                 } else {
@@ -70,30 +301,389 @@ impl Genome {
                 } else {
                     Reaction { rate: rng.gen(), .. r.clone() }
                 }),
-                Gene::Receptor(ref r) => Gene::Receptor(match rng.gen_range(0, 7) {
-                    1 => Receptor { kind: rng.gen(), .. *r },
-                    2 => Receptor { chemical: rng.gen(), .. *r },
-                    3 => Receptor { locus: rng.gen(), .. *r },
-                    4 => Receptor { nominal: rng.gen(), .. *r },
-                    5 => Receptor { gain: rng.gen(), .. *r },
-                    6 => Receptor { threshold: rng.gen(), .. *r },
-                    _ => Receptor { invert: rng.gen(), .. *r },
+                Gene::Receptor(ref r) => Gene::Receptor(match rng.gen_range(0, 8) {
+                    1 => Receptor { kind: rng.gen(), .. r.clone() },
+                    2 => Receptor { chemical: rng.gen(), .. r.clone() },
+                    3 => Receptor { locus: rng.gen(), .. r.clone() },
+                    4 => Receptor { nominal: rng.gen(), .. r.clone() },
+                    5 => Receptor { gain: rng.gen(), .. r.clone() },
+                    6 => Receptor { threshold: rng.gen(), .. r.clone() },
+                    7 => Receptor { sense: rng.gen(), .. r.clone() },
+                    _ => Receptor { invert: rng.gen(), .. r.clone() },
                 }),
-                _ => panic!("Something went wrong: failed to mutate a gene.")
+                Gene::ReactionGroup(ref g) => {
+                    let mut g = g.clone();
+                    if !g.kinds.is_empty() && rng.gen() {
+                        let i = rng.gen_range(0, g.kinds.len());
+                        g.kinds[i] = rng.gen();
+                    } else {
+                        g.rate = rng.gen();
+                    }
+                    Gene::ReactionGroup(g)
+                },
+                Gene::Custom(ref c) => Gene::Custom(c.clone()),
+                Gene::Homeostat(ref h) => Gene::Homeostat(match rng.gen_range(0, 3) {
+                    1 => Homeostat { target: rng.gen_range(0.0, 255.0), .. h.clone() },
+                    2 => Homeostat { rate: rng.gen_range(0.0, 1.0), .. h.clone() },
+                    _ => Homeostat { chemical: rng.gen(), .. h.clone() },
+                }),
+                Gene::Exchange(ref x) => Gene::Exchange(match rng.gen_range(0, 3) {
+                    1 => Exchange { from: rng.gen(), .. x.clone() },
+                    2 => Exchange { to: rng.gen(), .. x.clone() },
+                    _ => Exchange { rate: rng.gen_range(0.0, 1.0), .. x.clone() },
+                }),
+                Gene::Brain(_, _, ref weights) if weights.is_empty() => return None,
+                Gene::Brain(inputs, outputs, ref weights) => {
+                    let mut weights = weights.clone();
+                    let i = rng.gen_range(0, weights.len());
+                    weights[i] += rng.gen_range(-1.0, 1.0);
+                    Gene::Brain(inputs, outputs, weights)
+                },
             };
+            let gene = match config.ids {
+                Some(ids) => in_range(&gene, ids),
+                None => gene,
+            };
+            let before = replace(&mut self.genes[val], gene.clone());
+            debug!("Mutation changed gene {}.", val);
+            Some(MutationEvent { index: val, before: Some(before), after: Some(gene) })
         }
-        self
     }
 
+    /// Keeps only the genes `f` returns true for, in their original order.
+    pub fn retain<F: FnMut(&Gene) -> bool>(&mut self, f: F) {
+        self.genes.retain(f)
+    }
+
+    /// Plays a recorded mutation back onto this genome.
+    pub fn apply_mutation(&mut self, event: &MutationEvent) {
+        match (&event.before, &event.after) {
+            (&None, &Some(ref gene)) => self.genes.push(gene.clone()),
+            (_, &Some(ref gene)) => self.genes[event.index] = gene.clone(),
+            (_, &None) => { self.genes.swap_remove(event.index); },
+        }
+    }
+
+    /// Produces a copy of this genome with every chemical id passed through `f`, which makes it
+    /// possible to merge genomes that were written with clashing ids.
+    pub fn map_chemicals<F: Fn(Id) -> Id>(&self, f: F) -> Genome {
+        Genome::from_genes(self.genes.iter().map(|gene| gene.map_chemicals(&f)).collect())
+    }
+
+    /// Appends a copy of `module`'s genes with `id_offset` added to every chemical id they refer
+    /// to, so that a module can be reused alongside chemicals it would otherwise clash with. If any
+    /// id would go past 255 the genome is left as it is and the lowest such id in `module` is
+    /// returned.
+    pub fn splice_module(&mut self, module: &Genome, id_offset: Id) -> StdResult<(), Id> {
+        let ids = module.referenced_chemicals();
+        if let Some(&id) = ids.iter().find(|id| id.checked_add(id_offset).is_none()) {
+            return Err(id)
+        }
+        self.genes.extend(module.map_chemicals(|id| id + id_offset).genes);
+        Ok(())
+    }
+
+    /// Measures how far apart two genomes are. This is an edit distance over the gene lists where
+    /// adding or removing a gene costs 1, and replacing one gene with another costs 1 unless they
+    /// are the same kind of gene, in which case it costs the mean difference between their
+    /// parameters scaled to 0-1. Identical genomes are 0 apart, and no two genomes are further
+    /// apart than the length of the longer one.
+    pub fn distance(a: &Genome, b: &Genome) -> f32 {
+        let mut row: Vec<f32> = (0 .. b.genes.len() + 1).map(|j| j as f32).collect();
+        for (i, x) in a.genes.iter().enumerate() {
+            let mut diagonal = row[0];
+            row[0] = (i + 1) as f32;
+            for (j, y) in b.genes.iter().enumerate() {
+                let replace = diagonal + gene_distance(x, y);
+                diagonal = row[j + 1];
+                row[j + 1] = replace.min(row[j] + 1.0).min(diagonal + 1.0);
+            }
+        }
+        row[b.genes.len()]
+    }
+
+    /// Looks for anything that would stop the genome from running properly, giving every problem
+    /// it finds in gene order.
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        for (i, gene) in self.genes.iter().enumerate() {
+            match *gene {
+                Gene::Reaction(ref r) => errors.extend(zero_coefficients(i, &[r.kind])),
+                Gene::ReactionGroup(ref g) => errors.extend(zero_coefficients(i, &g.kinds)),
+                Gene::Receptor(Receptor { sense: ReceptorType::Linear(ref terms), .. }) => {
+                    errors.extend(terms.iter().filter(|t| !t.1.is_finite()).map(|t| {
+                        ValidationError::NonFiniteWeight(i, t.0)
+                    }));
+                },
+                _ => (),
+            }
+            if let Gene::Receptor(_) = *gene { continue }
+            if weights(gene).iter().any(|w| !w.is_finite()) {
+                errors.push(ValidationError::NonFiniteParameter(i));
+            }
+        }
+        errors
+    }
+
+    /// Compares two genomes gene by gene, allowing the float weights of linear receptors and
+    /// brains, the rate constants of mass-action reactions, the targets and rates of homeostats and
+    /// the rates of exchanges to differ by up to `epsilon`.
+    /// Everything else, tick counters aside, has to match exactly.
+    pub fn approx_eq(&self, other: &Genome, epsilon: f32) -> bool {
+        self.genes.len() == other.genes.len() &&
+            self.genes.iter().zip(other.genes.iter()).all(|(a, b)| gene_approx_eq(a, b, epsilon))
+    }
+
+    /// A size score for penalising bloated genomes in fitness functions. Initial states count 0.5,
+    /// emitters, homeostats, receptors and custom genes 1, reactions and exchanges 2 since they tie
+    /// several chemicals together (so a reaction group 2 for each of its reactions), and brains 1
+    /// plus 0.1 for every weight.
+    pub fn complexity(&self) -> f32 {
+        self.genes.iter().map(|gene| match *gene {
+            Gene::InitialState(_) => 0.5,
+            Gene::Emitter(_) | Gene::Homeostat(_) | Gene::Receptor(_) | Gene::Custom(_) => 1.0,
+            Gene::Reaction(_) | Gene::Exchange(_) => 2.0,
+            Gene::ReactionGroup(ref g) => 2.0 * g.kinds.len() as f32,
+            Gene::Brain(_, _, ref weights) => 1.0 + 0.1 * weights.len() as f32,
+        }).sum()
+    }
+
+    /// Every chemical id that any gene refers to.
+    pub fn referenced_chemicals(&self) -> BTreeSet<Id> {
+        self.genes.iter().flat_map(|gene| gene_chemicals(gene).into_iter()).collect()
+    }
+
+    /// How many genes refer to the chemical `id`, counting each gene once however many times it
+    /// mentions it.
+    pub fn count_references(&self, id: Id) -> usize {
+        self.genes.iter().filter(|gene| gene_chemicals(gene).contains(&id)).count()
+    }
+
+    /// Whether two genomes share enough chemicals to be worth recombining: the ids they have in
+    /// common must make up at least `threshold` (0-1) of all the ids either one uses. Two genomes
+    /// that use no chemicals at all are always compatible.
+    pub fn is_compatible(&self, other: &Genome, threshold: f32) -> bool {
+        let ours = self.referenced_chemicals();
+        let theirs = other.referenced_chemicals();
+        let union = ours.union(&theirs).count();
+        if union == 0 { return true }
+        ours.intersection(&theirs).count() as f32 / union as f32 >= threshold
+    }
+
+    /// Single-point crossover: the child takes this genome's genes up to a random point and the
+    /// other's from a second random point on.
+    pub fn crossover<R: Rng>(&self, other: &Genome, rng: &mut R) -> Genome {
+        let ours = rng.gen_range(0, self.genes.len() + 1);
+        let theirs = rng.gen_range(0, other.genes.len() + 1);
+        Genome::from_genes(self.genes[.. ours].iter().chain(other.genes[theirs ..].iter())
+                                              .cloned().collect())
+    }
+
+    /// Like `crossover`, but never cuts this genome before any of the genes at the indices in
+    /// `frozen`, so the child keeps all of them where they were.
+    pub fn crossover_frozen<R: Rng>(&self, other: &Genome, frozen: &HashSet<usize>, rng: &mut R)
+        -> Genome {
+        let first = frozen.iter().map(|&i| i + 1).max().unwrap_or(0).min(self.genes.len());
+        let ours = rng.gen_range(first, self.genes.len() + 1);
+        let theirs = rng.gen_range(0, other.genes.len() + 1);
+        Genome::from_genes(self.genes[.. ours].iter().chain(other.genes[theirs ..].iter())
+                                              .cloned().collect())
+    }
+
+    /// Like `crossover`, but gives up unless the genomes pass `is_compatible` with `threshold`.
+    pub fn crossover_compatible<R: Rng>(&self, other: &Genome, threshold: f32, rng: &mut R)
+        -> Option<Genome> {
+        if self.is_compatible(other, threshold) {
+            Some(self.crossover(other, rng))
+        } else {
+            None
+        }
+    }
+
+    /// The reactions' stoichiometry as a matrix for linear analysis. There is a row for every
+    /// chemical any reaction mentions, in the order of the returned ids, and a column for every
+    /// reaction, in genome order, with each of a group's reactions counted apart. Each entry is
+    /// the net amount of the chemical one run of the reaction makes (negative if it is used up)
+    /// times how often the reaction runs, which is once every `rate` ticks (or every tick for a
+    /// rate of 0).
+    pub fn stoichiometry(&self) -> (Vec<Id>, Vec<Vec<f32>>) {
+        let reactions: Vec<_> = self.genes.iter().flat_map(|gene| match *gene {
+            Gene::Reaction(ref r) => vec![r.clone()],
+            Gene::ReactionGroup(ref g) => g.reactions(),
+            _ => vec![]
+        }).collect();
+        let ids: Vec<Id> = reactions.iter().flat_map(|r| {
+            r.kind.chemicals().into_iter().map(|c| c.id())
+        }).collect::<BTreeSet<_>>().into_iter().collect();
+        let columns: Vec<_> = reactions.iter().map(|r| {
+            (r.coefficients(), 1.0 / r.rate.max(1) as f32)
+        }).collect();
+        let matrix = ids.iter().map(|id| {
+            columns.iter().map(|&(ref coefficients, frequency)| {
+                *coefficients.get(id).unwrap_or(&0) as f32 * frequency
+            }).collect()
+        }).collect();
+        (ids, matrix)
+    }
+
+    /// Sorts the genes into a fixed order so that genomes holding the same genes compare equal no
+    /// matter how they were arranged: initial states, then emitters, reactions, receptors and
+    /// brains, each ordered by their parameters (reactions by variant, then by the chemicals they
+    /// involve). Genes run in order within a step, so the sorted genome may behave differently.
+    pub fn canonicalize(&self) -> Genome {
+        let mut keyed: Vec<_> = self.genes.iter().map(|gene| {
+            let (kind, params) = parameters(gene);
+            (gene_rank(gene), kind, params, gene.clone())
+        }).collect();
+        keyed.sort_by(|a, b| {
+            (a.0, a.1).cmp(&(b.0, b.1)).then(a.2.partial_cmp(&b.2).unwrap_or(Ordering::Equal))
+        });
+        Genome::from_genes(keyed.into_iter().map(|(_, _, _, gene)| gene).collect())
+    }
+
+    /// Finds closed loops of reactions, where each reaction makes something the next one consumes
+    /// and the last feeds back into the first. Each cycle is given as gene indices, starting from
+    /// its lowest index. Catalysts are not counted as consumed, so they never close a loop. Each
+    /// reaction in a group counts on its own, so a loop through several of a group's reactions
+    /// names the group once for each of them.
+    pub fn reaction_cycles(&self) -> Vec<Vec<usize>> {
+        let reactions: Vec<_> = self.genes.iter().enumerate().flat_map(|(i, gene)| {
+            let kinds = match *gene {
+                Gene::Reaction(ref r) => vec![r.kind],
+                Gene::ReactionGroup(ref g) => g.kinds.clone(),
+                _ => vec![]
+            };
+            kinds.into_iter().map(move |kind| (i, reactant_ids(&kind), product_ids(&kind)))
+        }).collect();
+        let feeds = |from: usize, to: usize| {
+            reactions[from].2.iter().any(|id| reactions[to].1.contains(id))
+        };
+        let mut cycles = Vec::new();
+        for start in 0 .. reactions.len() {
+            find_cycles(reactions.len(), &mut vec![start], &feeds, &mut cycles);
+        }
+        cycles.into_iter().map(|cycle| {
+            cycle.into_iter().map(|n| reactions[n].0).collect()
+        }).collect()
+    }
+
+    /// Every chemical the genome's reactions and exchanges can make, directly or through each
+    /// other, starting from `sources` alone. A reaction counts once all of its inputs, catalysts
+    /// included, can be had, and then makes all of its products. Only which chemicals can appear
+    /// is tracked, not how much of them. Emitters and initial states are not followed, so
+    /// chemicals they provide have to be among the sources.
+    pub fn reachable_from(&self, sources: &[Id]) -> BTreeSet<Id> {
+        let mut steps: Vec<(Vec<Id>, Vec<Id>)> = Vec::new();
+        for gene in self.genes.iter() {
+            let kinds = match *gene {
+                Gene::Reaction(ref r) => vec![r.kind],
+                Gene::ReactionGroup(ref g) => g.kinds.clone(),
+                Gene::Exchange(ref x) => {
+                    steps.push((vec![x.from], vec![x.to]));
+                    continue
+                },
+                _ => continue
+            };
+            steps.extend(kinds.iter().map(|kind| {
+                (kind.inputs().iter().map(|c| c.id()).collect(), product_ids(kind))
+            }));
+        }
+        let mut reachable: BTreeSet<Id> = sources.iter().cloned().collect();
+        loop {
+            let before = reachable.len();
+            for &(ref inputs, ref products) in steps.iter() {
+                if inputs.iter().all(|id| reachable.contains(id)) {
+                    reachable.extend(products.iter().cloned());
+                }
+            }
+            if reachable.len() == before { return reachable }
+        }
+    }
+
+    /// Draws the genome as a Graphviz graph. Every chemical a gene refers to is an ellipse labelled
+    /// with its id, and every emitter, homeostat, reaction, reaction group and receptor is a node
+    /// labelled with its gene index: reactions and groups are boxes with edges in from their
+    /// reactants and out to their products (catalysts dashed), exchanges are boxes in the path from
+    /// what they move to where it goes, emitters point at what they make, homeostats have a two-way
+    /// edge with what they regulate and receptors are pointed at by what they sense.
+    pub fn to_dot(&self) -> String {
+        self.to_dot_named(&HashMap::new())
+    }
+
+    /// Like `to_dot`, but labels the chemicals that have a name in `names` with it.
+    pub fn to_dot_named(&self, names: &HashMap<Id, String>) -> String {
+        let mut dot = "digraph genome {\n".to_owned();
+        for id in self.referenced_chemicals() {
+            let label = names.get(&id).cloned().unwrap_or_else(|| id.to_string());
+            dot.push_str(&format!("    chem{} [label={:?}];\n", id, label));
+        }
+        for (i, gene) in self.genes.iter().enumerate() {
+            let (kind, shape) = match *gene {
+                Gene::Emitter(_) => ("emitter", "invhouse"),
+                Gene::Homeostat(_) => ("homeostat", "diamond"),
+                Gene::Reaction(_) => ("reaction", "box"),
+                Gene::ReactionGroup(_) => ("reaction group", "box"),
+                Gene::Exchange(_) => ("exchange", "box"),
+                Gene::Receptor(_) => ("receptor", "house"),
+                _ => continue
+            };
+            dot.push_str(&format!("    gene{} [label=\"{} {}\", shape={}];\n", i, kind, i, shape));
+            match *gene {
+                Gene::Emitter(ref e) =>
+                    dot.push_str(&format!("    gene{} -> chem{};\n", i, e.chemical)),
+                Gene::Homeostat(ref h) =>
+                    dot.push_str(&format!("    gene{} -> chem{} [dir=both];\n", i, h.chemical)),
+                Gene::Reaction(ref r) => dot.push_str(&reaction_edges(i, &[r.kind])),
+                Gene::ReactionGroup(ref g) => dot.push_str(&reaction_edges(i, &g.kinds)),
+                Gene::Exchange(ref x) => dot.push_str(&format!(
+                    "    chem{} -> gene{};\n    gene{} -> chem{};\n", x.from, i, i, x.to
+                )),
+                _ => {
+                    let sensed: BTreeSet<_> = gene_chemicals(gene).into_iter().collect();
+                    for id in sensed {
+                        dot.push_str(&format!("    chem{} -> gene{};\n", id, i));
+                    }
+                },
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Reads a genome saved by `save`. JSON has no way to write infinity or NaN, so encoders put
+    /// other values in their place (`null`, for one) which decode as NaN; genomes with any of
+    /// those, or anything else `validate` finds, are turned away with the first problem found
+    /// rather than left to break stepping.
+    ///
+    /// Genomes saved by newer versions load as long as they only add fields, as fields this version
+    /// does not know are ignored. New kinds of gene or reaction cannot be read, and fail to decode.
+    /// Genomes saved by older versions load with defaults for the fields added since: `None` for
+    /// those holding an `Option`, and what `Reaction::new` or `Receptor::new` gives for the
+    /// settings reactions and receptors gained later. Any other new field has to be optional or
+    /// decode with a default in the same way, or older genomes stop loading.
     pub fn load<T: AsRef<Path>>(path: T) -> Result<Genome> {
         let mut f = try!(File::open(path.as_ref()));
         let mut data = String::new();
         try!(f.read_to_string(&mut data));
-        decode(&data).map_err(|_|
-            Error::new(ErrorKind::InvalidInput, "Failed to decode genome.")
-        )
+        let genome = try!(Genome::try_from_json(&data).map_err(|e|
+            Error::new(ErrorKind::InvalidInput, format!("Failed to decode genome: {}", e))
+        ));
+        match genome.validate().into_iter().next() {
+            Some(e) => Err(Error::new(ErrorKind::InvalidInput, format!("Invalid genome: {}.", e))),
+            None => Ok(genome),
+        }
     }
 
+    /// Reads a genome from JSON, keeping what went wrong if it cannot. Syntax errors give the
+    /// line and column they were found at, and other errors the field or value that was wrong.
+    pub fn try_from_json(json: &str) -> StdResult<Genome, DecoderError> {
+        decode(json)
+    }
+
+    /// Writes the genome out as JSON. Tick counters and what receptors last sensed and how long
+    /// they have left to rest are written too, so a genome saved partway through a run and loaded
+    /// again fires on exactly the steps it would have fired on without the round trip. Only
+    /// reaction throughput is left out.
     pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<()> {
         let mut f = try!(File::create(path.as_ref()));
         try!(f.write_all(try!(encode(self).map_err(|_|
@@ -102,6 +692,24 @@ impl Genome {
         f.flush()
     }
 
+    /// Saves like `save`, but refuses with an error, leaving the file alone, if it has been
+    /// modified since `since`, so that processes sharing a file cannot silently undo each other's
+    /// work. Pass the file's modification time from when it was loaded, or the time returned by the
+    /// last call, which is the modification time of the file just written.
+    pub fn save_unless_modified<T: AsRef<Path>>(&self, path: T, since: SystemTime)
+        -> Result<SystemTime> {
+        let path = path.as_ref();
+        match fs::metadata(path).and_then(|meta| meta.modified()) {
+            Ok(modified) if modified > since => return Err(Error::new(ErrorKind::Other,
+                "Genome file was modified by something else since it was last read or written.")),
+            Err(ref e) if e.kind() != ErrorKind::NotFound => return Err(Error::new(e.kind(),
+                "Failed to read the genome file's modification time.")),
+            _ => (),
+        }
+        try!(self.save(path));
+        fs::metadata(path).and_then(|meta| meta.modified())
+    }
+
     pub fn init(&self, creature: &mut Creature) {
         for gene in self.genes.iter() {
             if let Gene::InitialState(ref c) = *gene {
@@ -110,14 +718,1844 @@ impl Genome {
         }
     }
 
+    /// Runs every gene once, in genome order. Genes make their changes as they go, so each one sees
+    /// what the genes before it did earlier in the same step (see `StepContext::sequential` for
+    /// the alternative). Concentrations are whole numbers
+    /// from 0 to 255, so they are always finite and in range. Debug builds also check that nothing
+    /// in the step took a chemical over its cap in the `StepContext`, panicking with its id if
+    /// something did, where release builds quietly bring it back down to the cap.
     pub fn step(&self, creature: &mut Creature) {
+        self.step_with(creature, &StepContext::new())
+    }
+
+    pub fn step_with(&self, creature: &mut Creature, ctx: &StepContext) {
+        self.step_reusing(creature, ctx, &mut Vec::new(), &mut DeltaBuffer::new())
+    }
+
+    /// Steps the creature once like `step`, and tells whether any concentration changed.
+    pub fn step_checked(&self, creature: &mut Creature) -> bool {
+        let before = creature.concentrations();
+        self.step(creature);
+        changed(&before, &creature.concentrations())
+    }
+
+    /// Steps the creature `n` times, which is the same as calling `step` `n` times.
+    pub fn step_n(&self, creature: &mut Creature, n: usize) {
+        self.step_n_with(creature, &StepContext::new(), n)
+    }
+
+    pub fn step_n_with(&self, creature: &mut Creature, ctx: &StepContext, n: usize) {
+        let mut before = Vec::with_capacity(ctx.buffers.len());
+        let mut deltas = DeltaBuffer::new();
+        for _ in 0 .. n {
+            self.step_reusing(creature, ctx, &mut before, &mut deltas);
+        }
+    }
+
+    /// Steps the creature once like `step`, collecting each reaction's changes in `deltas` instead
+    /// of a fresh map. Reusing one buffer over many steps saves allocating for every reaction.
+    pub fn step_dense(&self, creature: &mut Creature, deltas: &mut DeltaBuffer) {
+        self.step_reusing(creature, &StepContext::new(), &mut Vec::new(), deltas)
+    }
+
+    fn step_reusing(&self, creature: &mut Creature, ctx: &StepContext,
+                    before: &mut Vec<Concentration>, deltas: &mut DeltaBuffer) {
+        before.clear();
+        before.extend(ctx.buffers.iter().map(|b| creature.chemo_body_mut().concnt(b.chemical)));
+        let scales = self.group_scales(creature, ctx);
+        let allotments = if ctx.share_reactants {
+            self.reaction_allotments(creature, ctx)
+        } else {
+            HashMap::new()
+        };
+        let start = if ctx.leak.is_some() { Some(creature.concentrations()) } else { None };
+        let snapshot = if ctx.sequential { None } else { Some(creature.chemo_body().clone()) };
+        let mut total = DeltaMap::new();
+        for (i, gene) in self.genes.iter().enumerate() {
+            if let Some(ref snapshot) = snapshot {
+                *creature.chemo_body_mut() = snapshot.clone();
+            }
+            match (gene, scales.get(&i), allotments.get(&i)) {
+                (&Gene::Emitter(ref e), Some(&scale), _) => e.step_scaled(creature, ctx, scale),
+                (&Gene::Reaction(ref r), _, limit) =>
+                    r.step_buffered(creature, ctx, limit.cloned().unwrap_or(255), deltas),
+                _ => step_gene(gene, creature, ctx),
+            }
+            if let Some(ref snapshot) = snapshot {
+                for (id, concnt) in creature.chemo_body().snapshot() {
+                    let change = total.entry(id).or_insert(0);
+                    *change = change.saturating_add(concnt as i16 - snapshot.peek(id) as i16);
+                }
+            }
+        }
+        let mut body = creature.chemo_body_mut();
+        if let Some(snapshot) = snapshot {
+            *body = snapshot;
+            body.apply(&total);
+        }
+        if let (Some(leak), Some(start)) = (ctx.leak, start) {
+            let mut carried = ctx.carried.borrow_mut();
+            body.apply(&carried);
+            *carried = body.snapshot().into_iter().filter_map(|(id, concnt)| {
+                let change = concnt as i16 - *start.get(&id).unwrap_or(&0) as i16;
+                let kept = (change as f32 * leak) as i16;
+                if kept != 0 { Some((id, kept)) } else { None }
+            }).collect();
+        }
+        for (&id, &rate) in ctx.decay_rates.iter() {
+            body.scale(id, (1.0 - rate).powf(ctx.dt));
+        }
+        for (buffer, before) in ctx.buffers.iter().zip(before.iter()) {
+            buffer.apply(&mut body, *before);
+        }
+        if let (Some(amplitude), Some(rng)) = (ctx.noise, ctx.rng.as_ref()) {
+            body.jitter(&mut *rng.borrow_mut(), amplitude);
+        }
+        if let Some(levels) = ctx.quantize {
+            body.quantize(levels);
+        }
+        for (&id, &most) in ctx.caps.iter() {
+            debug_assert!(body.peek(id) <= most, "Chemical {} reached {}, over its cap of {}.", id,
+                          body.peek(id), most);
+            body.cap(id, most);
+        }
+    }
+
+    /// Steps a copy of the genome on a creature holding `initial` until its concentrations settle,
+    /// and gives where they settled. Each iteration runs as many steps as the slowest emitter or
+    /// reaction's rate so that every gene gets to fire, and the concentrations count as settled
+    /// once they move less than `tolerance` (as a Euclidean distance) over an iteration. Gives
+    /// `None` if that does not happen within `max_iter` iterations. Genes firing at rates that do
+    /// not divide evenly can leave a small wobble, which `tolerance` needs to allow for.
+    pub fn equilibrium(&self, initial: &HashMap<Id, Concentration>, tolerance: f32,
+                       max_iter: usize) -> Option<HashMap<Id, Concentration>> {
+        let genome = self.clone();
+        let mut creature = creature_with(initial);
+        let window = self.firing_window();
+        let mut before = creature.concentrations();
+        for _ in 0 .. max_iter {
+            genome.step_n(&mut creature, window);
+            let after = creature.concentrations();
+            let norm = after.iter().map(|(id, &concnt)| {
+                let change = concnt as f32 - *before.get(id).unwrap_or(&0) as f32;
+                change * change
+            }).sum::<f32>().sqrt();
+            if norm < tolerance { return Some(after) }
+            before = after;
+        }
+        None
+    }
+
+    /// Describes how the genome behaves rather than how it is built. A copy of the genome runs
+    /// for `ticks` steps on a fresh creature that was given its initial state and then
+    /// `stimulus`, and the result is the final concentration of every possible chemical id, in
+    /// order and scaled to 0-1, so any two phenotypes can be compared directly.
+    pub fn phenotype(&self, stimulus: &[(Id, Concentration)], ticks: usize) -> Vec<f32> {
+        let mut creature = Creature::new();
+        self.init(&mut creature);
+        for &(id, amount) in stimulus.iter() {
+            creature.inject(id, amount);
+        }
+        self.clone().step_n(&mut creature, ticks);
+        let body = creature.chemo_body();
+        (0 .. 256).map(|id| body.peek(id as Id) as f32 / 255.0).collect()
+    }
+
+    /// Whether a copy of the genome leaves the concentrations in `state` exactly as they are over
+    /// as many steps as the slowest emitter or reaction's rate, so that every gene gets to fire.
+    pub fn is_quiescent(&self, state: &HashMap<Id, Concentration>) -> bool {
+        let mut creature = creature_with(state);
+        let before = creature.concentrations();
+        self.clone().step_n(&mut creature, self.firing_window());
+        !changed(&before, &creature.concentrations())
+    }
+
+    /// Whether a copy of the genome, run for `ticks` steps from the concentrations in `initial`,
+    /// ends up with some chemical stuck at 255. The chemical has to be at 255 after each of the
+    /// last `threshold` fraction of the steps (at least the last one), so a chemical that only
+    /// touches the top on its way back down does not count.
+    pub fn is_runaway(&self, initial: &HashMap<Id, Concentration>, ticks: usize, threshold: f32)
+        -> bool {
+        let genome = self.clone();
+        let mut creature = creature_with(initial);
+        let tail = ((ticks as f32 * threshold).ceil() as usize).max(1);
+        let mut pinned: Option<BTreeSet<Id>> = None;
+        for t in 0 .. ticks {
+            genome.step(&mut creature);
+            if t + tail < ticks { continue }
+            let full: BTreeSet<_> = creature.concentrations().into_iter()
+                .filter(|&(_, concnt)| concnt == 255).map(|(id, _)| id).collect();
+            pinned = Some(match pinned {
+                None => full,
+                Some(pinned) => pinned.intersection(&full).cloned().collect(),
+            });
+        }
+        pinned.map_or(false, |pinned| !pinned.is_empty())
+    }
+
+    /// The number of steps it takes every emitter, reaction and reaction group to fire at least
+    /// once.
+    fn firing_window(&self) -> usize {
+        self.genes.iter().map(|gene| match *gene {
+            Gene::Emitter(ref e) => e.rate as usize,
+            Gene::Reaction(ref r) => r.rate as usize,
+            Gene::ReactionGroup(ref g) => g.rate as usize,
+            _ => 1
+        }).max().unwrap_or(1).max(1)
+    }
+
+    /// How much to scale each grouped emitter that fires in the coming step so that no group goes
+    /// over its budget, keyed by gene index. Emitters that can keep their full gain are left out,
+    /// and an emitter in several groups takes the smallest of their scales.
+    fn group_scales(&self, creature: &Creature, ctx: &StepContext) -> HashMap<usize, f32> {
+        let mut scales = HashMap::new();
+        for group in ctx.emitter_groups.iter() {
+            let firing: Vec<_> = group.genes.iter().filter_map(|&i| match self.genes.get(i) {
+                Some(&Gene::Emitter(ref e)) if e.fires(ctx.dt) =>
+                    Some((i, e.current_gain(creature))),
+                _ => None
+            }).collect();
+            let total = firing.iter().map(|&(_, gain)| gain as u32).sum::<u32>();
+            if total <= group.budget as u32 { continue }
+            let scale = group.budget as f32 / total as f32;
+            for (i, _) in firing {
+                let least = scales.get(&i).map_or(scale, |&other: &f32| other.min(scale));
+                scales.insert(i, least);
+            }
+        }
+        scales
+    }
+
+    /// How many times each reaction that fires in the coming step may run when reactants are
+    /// shared, keyed by gene index. See `StepContext::share_reactants`.
+    fn reaction_allotments(&self, creature: &Creature, ctx: &StepContext) -> HashMap<usize, u8> {
+        let body = creature.chemo_body();
+        let firing: Vec<_> = self.genes.iter().enumerate().filter_map(|(i, gene)| match *gene {
+            Gene::Reaction(ref r) if r.fires(ctx.dt) =>
+                Some((i, r.extent(&body), r.coefficients())),
+            _ => None
+        }).collect();
+        let mut demand: HashMap<Id, u32> = HashMap::new();
+        for &(_, n, ref coefficients) in firing.iter() {
+            for (&id, &c) in coefficients.iter().filter(|&(_, &c)| c < 0) {
+                *demand.entry(id).or_insert(0) += n as u32 * (-c) as u32;
+            }
+        }
+        firing.into_iter().map(|(i, n, coefficients)| {
+            let share = coefficients.iter().filter(|&(_, &c)| c < 0).map(|(id, _)| {
+                let supply = body.peek(*id) as f32;
+                let wanted = demand[id] as f32;
+                if wanted > supply { supply / wanted } else { 1.0 }
+            }).fold(1.0, f32::min);
+            (i, (n as f32 * share) as u8)
+        }).collect()
+    }
+
+    /// Works out when each receptor fired from concentrations recorded after every step of a run,
+    /// without stepping anything. Receptors read concentrations partway through a real step, so
+    /// this only agrees with the run for receptors whose chemicals no later gene changes. Receptors
+    /// start out from what they last sensed and the rest they have left now.
+    pub fn replay_history(&self, history: &[HashMap<Id, Concentration>]) -> Vec<ReceptorFiring> {
+        let mut receptors: Vec<_> = self.genes.iter().enumerate().filter_map(|(i, gene)| {
+            match *gene {
+                Gene::Receptor(ref r) => Some((i, r, r.last.get(), r.resting.get())),
+                _ => None
+            }
+        }).collect();
+        let mut firings = Vec::new();
+        for (tick, concentrations) in history.iter().enumerate() {
+            for receptor in receptors.iter_mut() {
+                let (gene, r) = (receptor.0, receptor.1);
+                let val = r.measure(|id| *concentrations.get(&id).unwrap_or(&0));
+                let (output, resting, fired) = r.respond(receptor.2, val, receptor.3);
+                if fired {
+                    firings.push(ReceptorFiring { tick: tick, gene: gene, output: output });
+                }
+                receptor.2 = val;
+                receptor.3 = resting;
+            }
+        }
+        firings
+    }
+
+    /// Sets the `throughput` of every reaction and reaction group back to zero.
+    pub fn reset_throughput(&self) {
         for gene in self.genes.iter() {
             match *gene {
-                Gene::Emitter(ref e) => e.step(creature),
-                Gene::Reaction(ref r) => r.step(creature),
-                Gene::Receptor(ref r) => r.step(creature),
-                _ => ()
+                Gene::Reaction(ref r) => r.reset_throughput(),
+                Gene::ReactionGroup(ref g) => g.reset_throughput(),
+                _ => {}
             }
         }
     }
+
+    /// Steps the creature once like `step`, recording how each gene changed its chemistry.
+    pub fn step_trace(&self, creature: &mut Creature) -> StepTrace {
+        let ctx = StepContext::new();
+        StepTrace {
+            deltas: self.genes.iter().map(|gene| {
+                let before = creature.chemo_body().snapshot();
+                step_gene(gene, creature, &ctx);
+                let after = creature.chemo_body().snapshot();
+                after.into_iter().filter_map(|(id, concnt)| {
+                    let old = *before.get(&id).unwrap_or(&0);
+                    if concnt != old { Some((id, concnt as i16 - old as i16)) } else { None }
+                }).collect()
+            }).collect()
+        }
+    }
+}
+
+impl FromIterator<Gene> for Genome {
+    fn from_iter<I: IntoIterator<Item = Gene>>(genes: I) -> Genome {
+        Genome::from_genes(genes.into_iter().collect())
+    }
+}
+
+/// A receptor firing at the end of step `tick`, found by `Genome::replay_history`. Receptors fire
+/// when they sense more than their threshold (or cross it, for edge receptors) while not resting.
+/// `gene` is the receptor's index and `output` what it would have written to its locus.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReceptorFiring {
+    pub tick: usize,
+    pub gene: usize,
+    pub output: LocusValue,
+}
+
+/// Something that stops a genome from running properly, found by `Genome::validate`. Each names
+/// the index of the offending gene and, where there is one, the chemical involved.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationError {
+    /// A reaction needs none of one of its inputs, so it cannot be sized and never runs.
+    ZeroCoefficient(usize, Id),
+    /// A linear receptor weights a chemical by infinity or NaN.
+    NonFiniteWeight(usize, Id),
+    /// Another of the gene's float parameters, such as a brain weight or a mass-action rate
+    /// constant, is infinity or NaN.
+    NonFiniteParameter(usize),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::ZeroCoefficient(gene, id) =>
+                write!(f, "gene {} needs none of chemical {}", gene, id),
+            ValidationError::NonFiniteWeight(gene, id) =>
+                write!(f, "gene {} weights chemical {} by infinity or NaN", gene, id),
+            ValidationError::NonFiniteParameter(gene) =>
+                write!(f, "gene {} has a parameter that is infinity or NaN", gene),
+        }
+    }
+}
+
+/// Puts a genome together gene by gene, checking it with `Genome::validate` once it is built.
+pub struct GenomeBuilder {
+    genes: Vec<Gene>
+}
+
+impl GenomeBuilder {
+    pub fn new() -> GenomeBuilder {
+        GenomeBuilder { genes: Vec::new() }
+    }
+
+    pub fn gene(mut self, gene: Gene) -> GenomeBuilder {
+        self.genes.push(gene);
+        self
+    }
+
+    pub fn initial_state(self, chemical: Chemical) -> GenomeBuilder {
+        self.gene(Gene::InitialState(chemical))
+    }
+
+    pub fn emitter(self, emitter: Emitter) -> GenomeBuilder {
+        self.gene(Gene::Emitter(emitter))
+    }
+
+    pub fn reaction(self, reaction: Reaction) -> GenomeBuilder {
+        self.gene(Gene::Reaction(reaction))
+    }
+
+    pub fn receptor(self, receptor: Receptor) -> GenomeBuilder {
+        self.gene(Gene::Receptor(receptor))
+    }
+
+    pub fn build(self) -> StdResult<Genome, Vec<ValidationError>> {
+        let genome = Genome::from_genes(self.genes);
+        let errors = genome.validate();
+        if errors.is_empty() { Ok(genome) } else { Err(errors) }
+    }
+}
+
+/// Writes a population out one genome per line, so that no more than one genome is ever held as
+/// text at once.
+pub fn write_population<W: Write>(genomes: &[Genome], mut writer: W) -> Result<()> {
+    for genome in genomes.iter() {
+        let line = try!(encode(genome).map_err(|_|
+            Error::new(ErrorKind::InvalidInput, "Failed to encode genome.")
+        ));
+        try!(writeln!(writer, "{}", line));
+    }
+    writer.flush()
+}
+
+/// Reads back a population written by `write_population`, a line at a time.
+pub fn read_population<R: BufRead>(reader: R) -> Result<Vec<Genome>> {
+    reader.lines().map(|line| line.and_then(|line| decode(&line).map_err(|_|
+        Error::new(ErrorKind::InvalidInput, "Failed to decode genome.")
+    ))).collect()
+}
+
+/// The change in concentration each gene caused during one step, indexed like the genome's genes.
+/// Chemicals a gene left untouched are absent from its map.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct StepTrace {
+    pub deltas: Vec<DeltaMap>,
+}
+
+fn step_gene(gene: &Gene, creature: &mut Creature, ctx: &StepContext) {
+    if let Some(steppable) = gene.as_steppable() {
+        steppable.step(creature, ctx);
+    }
+}
+
+/// Whether any concentration differs between two snapshots. Chemicals missing from `before` count
+/// as zero, since stepping can register chemicals that were never given.
+fn changed(before: &HashMap<Id, Concentration>, after: &HashMap<Id, Concentration>) -> bool {
+    after.iter().any(|(id, &concnt)| concnt != *before.get(id).unwrap_or(&0))
+}
+
+/// Folds every chemical id in the gene into `ids.0` to `ids.1` inclusive, leaving ids already in
+/// the range alone. The bounds may be given either way round.
+fn in_range(gene: &Gene, ids: (Id, Id)) -> Gene {
+    let (lo, hi) = (ids.0.min(ids.1) as u16, ids.0.max(ids.1) as u16);
+    gene.map_chemicals(|id| {
+        if id as u16 >= lo && id as u16 <= hi { id } else { (lo + id as u16 % (hi - lo + 1)) as u8 }
+    })
+}
+
+fn map_group<F: Fn(Id) -> Id>(ids: &Option<Vec<Id>>, f: F) -> Option<Vec<Id>> {
+    ids.as_ref().map(|ids| ids.iter().map(|&id| f(id)).collect())
+}
+
+fn zero_coefficients(gene: usize, kinds: &[ReactionType]) -> Vec<ValidationError> {
+    kinds.iter().flat_map(|kind| kind.inputs().into_iter()).filter(|c| c.concnt() == 0).map(|c| {
+        ValidationError::ZeroCoefficient(gene, c.id())
+    }).collect()
+}
+
+fn creature_with(concentrations: &HashMap<Id, Concentration>) -> Creature {
+    let creature = Creature::new();
+    for (&id, &concnt) in concentrations.iter() {
+        creature.inject(id, concnt);
+    }
+    creature
+}
+
+fn gene_chemicals(gene: &Gene) -> Vec<Id> {
+    match *gene {
+        Gene::InitialState(ref c) => vec![c.id()],
+        Gene::Emitter(ref e) => {
+            let mut ids = vec![e.chemical];
+            ids.extend(e.relative_to.iter().flat_map(|group| group.iter().cloned()));
+            ids
+        },
+        Gene::Homeostat(ref h) => vec![h.chemical],
+        Gene::Exchange(ref x) => vec![x.from, x.to],
+        Gene::Reaction(ref r) => r.kind.chemicals().iter().map(|c| c.id()).collect(),
+        Gene::ReactionGroup(ref g) =>
+            g.kinds.iter().flat_map(|kind| kind.chemicals().into_iter().map(|c| c.id())).collect(),
+        Gene::Receptor(ref r) => {
+            let mut ids = vec![r.chemical];
+            match r.sense {
+                ReceptorType::Single => (),
+                ReceptorType::Coincidence(other) => ids.push(other),
+                ReceptorType::Linear(ref terms) => ids.extend(terms.iter().map(|t| t.0)),
+            }
+            ids.extend(r.relative_to.iter().flat_map(|group| group.iter().cloned()));
+            ids
+        },
+        Gene::Brain(..) | Gene::Custom(_) => vec![],
+    }
+}
+
+fn gene_rank(gene: &Gene) -> u8 {
+    match *gene {
+        Gene::InitialState(_) => 0,
+        Gene::Emitter(_) | Gene::Homeostat(_) => 1,
+        Gene::Reaction(_) | Gene::ReactionGroup(_) | Gene::Exchange(_) => 2,
+        Gene::Receptor(_) => 3,
+        Gene::Brain(..) => 4,
+        Gene::Custom(_) => 5,
+    }
+}
+
+fn gene_approx_eq(a: &Gene, b: &Gene, epsilon: f32) -> bool {
+    if let (&Gene::Custom(ref x), &Gene::Custom(ref y)) = (a, b) {
+        return x.tag == y.tag && x.params == y.params
+    }
+    let (weights_a, weights_b) = (weights(a), weights(b));
+    parameters(&without_weights(a)) == parameters(&without_weights(b)) &&
+        weights_a.len() == weights_b.len() &&
+        weights_a.iter().zip(weights_b.iter()).all(|(x, y)| (x - y).abs() <= epsilon)
+}
+
+/// The float weights in a gene, which are the only parameters not made from whole numbers.
+fn weights(gene: &Gene) -> Vec<f32> {
+    match *gene {
+        Gene::Reaction(Reaction { kinetics: Kinetics::MassAction(k), .. }) => vec![k],
+        Gene::Receptor(Receptor { sense: ReceptorType::Linear(ref terms), .. }) =>
+            terms.iter().map(|t| t.1).collect(),
+        Gene::Brain(_, _, ref weights) => weights.clone(),
+        Gene::Homeostat(ref h) => vec![h.target, h.rate],
+        Gene::Exchange(ref x) => vec![x.rate],
+        _ => vec![],
+    }
+}
+
+fn without_weights(gene: &Gene) -> Gene {
+    match *gene {
+        Gene::Reaction(ref r @ Reaction { kinetics: Kinetics::MassAction(_), .. }) =>
+            Gene::Reaction(Reaction { kinetics: Kinetics::MassAction(0.0), .. r.clone() }),
+        Gene::Receptor(ref r) => {
+            let sense = match r.sense {
+                ReceptorType::Linear(ref terms) =>
+                    ReceptorType::Linear(terms.iter().map(|t| (t.0, 0.0)).collect()),
+                ref sense => sense.clone(),
+            };
+            Gene::Receptor(Receptor { sense: sense, .. r.clone() })
+        },
+        Gene::Brain(inputs, outputs, _) => Gene::Brain(inputs, outputs, vec![]),
+        Gene::Homeostat(ref h) => Gene::Homeostat(Homeostat::new(h.chemical, 0.0, 0.0)),
+        Gene::Exchange(ref x) => Gene::Exchange(Exchange::new(x.from, x.to, 0.0)),
+        ref gene => gene.clone(),
+    }
+}
+
+fn gene_distance(a: &Gene, b: &Gene) -> f32 {
+    let (kind_a, params_a) = parameters(a);
+    let (kind_b, params_b) = parameters(b);
+    if kind_a != kind_b || params_a.len() != params_b.len() { return 1.0 }
+    if params_a.is_empty() { return 0.0 }
+    params_a.iter().zip(params_b.iter()).map(|(x, y)| (x - y).abs().min(1.0)).sum::<f32>()
+        / params_a.len() as f32
+}
+
+/// Breaks a gene down into a kind, which tells apart genes and reaction variants that cannot be
+/// compared with each other, and its numeric parameters scaled into 0-1 where possible.
+fn parameters(gene: &Gene) -> (u8, Vec<f32>) {
+    let n = |v: u8| v as f32 / 255.0;
+    let flag = |b: bool| if b { 1.0 } else { 0.0 };
+    let io = |kind: IoType| match kind { IoType::Analogue => 0.0, IoType::Digital => 1.0 };
+    let group = |ids: &Option<Vec<Id>>| match *ids {
+        Some(ref ids) => Some(1.0).into_iter().chain(ids.iter().map(|&id| n(id))).collect(),
+        None => vec![0.0],
+    };
+    let chems = |chems: &[&Chemical]| chems.iter().flat_map(|c| {
+        vec![n(c.id()), n(c.concnt())].into_iter()
+    }).collect::<Vec<_>>();
+    let reaction = |kind: &ReactionType| match *kind {
+        ReactionType::Normal(ref a, ref b, ref c, ref d) => (0, chems(&[a, b, c, d])),
+        ReactionType::Fusion(ref a, ref b, ref c) => (1, chems(&[a, b, c])),
+        ReactionType::Decay(ref a) => (2, chems(&[a])),
+        ReactionType::Catalytic(ref a, ref b, ref c) => (3, chems(&[a, b, c])),
+        ReactionType::CatalyticBreakdown(ref a, ref b) => (4, chems(&[a, b])),
+        ReactionType::Split(ref a, ref b, ref c) => (5, chems(&[a, b, c])),
+        ReactionType::Autocatalytic(ref a, ref b) => (6, chems(&[a, b])),
+    };
+    match *gene {
+        Gene::InitialState(ref c) => (0, chems(&[c])),
+        Gene::Emitter(ref e) => {
+            let mut params = vec![io(e.kind), n(e.chemical), n(e.rate), n(e.gain), n(e.locus),
+                                  n(e.threshold), flag(e.clear_after_read), flag(e.invert),
+                                  flag(e.schedule.is_some())];
+            let schedule = e.schedule.map_or([0; 7], |s| s.0);
+            params.extend(schedule.iter().map(|g| n(*g)));
+            params.extend(group(&e.relative_to));
+            (1, params)
+        },
+        Gene::Reaction(ref r) => {
+            let (variant, mut params) = reaction(&r.kind);
+            params.push(n(r.rate));
+            params.push(flag(r.stochastic));
+            params.push(flag(r.catalyst_scaled));
+            match r.kinetics {
+                Kinetics::LimitingReagent => params.extend(vec![0.0, 0.0]),
+                Kinetics::MassAction(k) => params.extend(vec![1.0, k]),
+            }
+            (10 + variant, params)
+        },
+        Gene::Receptor(ref r) => {
+            let mut params = vec![io(r.kind), n(r.chemical), n(r.locus), n(r.nominal), n(r.gain),
+                                  n(r.threshold), flag(r.invert), n(r.refractory),
+                                  flag(r.edge)];
+            let sense = match r.sense {
+                ReceptorType::Single => 0,
+                ReceptorType::Coincidence(other) => {
+                    params.push(n(other));
+                    1
+                },
+                ReceptorType::Linear(ref terms) => {
+                    for &(id, weight) in terms.iter() {
+                        params.push(n(id));
+                        params.push(weight);
+                    }
+                    2
+                },
+            };
+            params.extend(group(&r.relative_to));
+            (20 + sense, params)
+        },
+        Gene::Brain(inputs, outputs, ref weights) => {
+            let mut params = vec![inputs as f32, outputs as f32];
+            params.extend(weights.iter().cloned());
+            (4, params)
+        },
+        Gene::ReactionGroup(ref g) => {
+            let mut params = vec![n(g.rate)];
+            for kind in g.kinds.iter() {
+                let (variant, chems) = reaction(kind);
+                params.push(variant as f32);
+                params.extend(chems);
+            }
+            (30, params)
+        },
+        Gene::Custom(_) => (5, vec![]),
+        Gene::Homeostat(ref h) => (6, vec![n(h.chemical), h.target / 255.0, h.rate]),
+        Gene::Exchange(ref x) => (7, vec![n(x.from), n(x.to), x.rate]),
+    }
+}
+
+fn reaction_edges(gene: usize, kinds: &[ReactionType]) -> String {
+    let mut edges = String::new();
+    for kind in kinds.iter() {
+        match *kind {
+            ReactionType::Catalytic(ref a, _, _) | ReactionType::CatalyticBreakdown(ref a, _) |
+            ReactionType::Autocatalytic(_, ref a) =>
+                edges.push_str(&format!("    chem{} -> gene{} [style=dashed];\n", a.id(), gene)),
+            _ => (),
+        }
+        for id in reactant_ids(kind) {
+            edges.push_str(&format!("    chem{} -> gene{};\n", id, gene));
+        }
+        for id in product_ids(kind) {
+            edges.push_str(&format!("    gene{} -> chem{};\n", gene, id));
+        }
+    }
+    edges
+}
+
+fn reactant_ids(kind: &ReactionType) -> Vec<Id> {
+    match *kind {
+        ReactionType::Normal(ref a, ref b, _, _) => vec![a.id(), b.id()],
+        ReactionType::Fusion(ref a, ref b, _) => vec![a.id(), b.id()],
+        ReactionType::Decay(ref a) | ReactionType::Split(ref a, _, _) |
+        ReactionType::Autocatalytic(ref a, _) => vec![a.id()],
+        ReactionType::Catalytic(_, ref b, _) => vec![b.id()],
+        ReactionType::CatalyticBreakdown(_, ref b) => vec![b.id()],
+    }
+}
+
+fn product_ids(kind: &ReactionType) -> Vec<Id> {
+    match *kind {
+        ReactionType::Normal(_, _, ref c, ref d) | ReactionType::Split(_, ref c, ref d) =>
+            vec![c.id(), d.id()],
+        ReactionType::Fusion(_, _, ref c) => vec![c.id()],
+        ReactionType::Catalytic(_, _, ref c) | ReactionType::Autocatalytic(_, ref c) =>
+            vec![c.id()],
+        _ => vec![],
+    }
+}
+
+/// Extends `path` with every reaction that closes or continues a loop back to `path[0]`. Only
+/// reactions after the start are visited, so each cycle is reported once.
+fn find_cycles<F>(count: usize, path: &mut Vec<usize>, feeds: &F, cycles: &mut Vec<Vec<usize>>)
+where F: Fn(usize, usize) -> bool {
+    let start = path[0];
+    let last = path[path.len() - 1];
+    for next in start .. count {
+        if !feeds(last, next) { continue }
+        if next == start {
+            cycles.push(path.clone());
+        } else if !path.contains(&next) {
+            path.push(next);
+            find_cycles(count, path, feeds, cycles);
+            path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::{env, fs, process, thread};
+    use std::collections::BTreeMap;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use chem::{AgeSchedule, Buffer, ChemoBody, Concentration, EmitterGroup, IoType,
+               ReactionType, ReceptorType};
+    use evolution::EvolutionLog;
+    use log::{self, Log, LogLevelFilter, LogMetadata, LogRecord};
+    use organism::Organism;
+    use rand::{SeedableRng, StdRng};
+    use rustc_serialize::json::{encode, ErrorCode, ParserError};
+    use super::*;
+
+    fn chem(id: Id, concnt: Concentration) -> Chemical {
+        Chemical::with_concentration(id, concnt)
+    }
+
+    fn rng(seed: usize) -> StdRng {
+        SeedableRng::from_seed(&[seed][..])
+    }
+
+    /// A path in the temporary directory that no other test process is using.
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("life-sim-{}-{}.json", name, process::id()))
+    }
+
+    fn concnt(creature: &Creature, id: Id) -> Concentration {
+        creature.chemo_body_mut().concnt(id)
+    }
+
+    #[test]
+    fn buffered_chemicals_change_less_per_step() {
+        let genome = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 1, 0, 100, 0, 0, false, false)),
+        ]);
+        let ctx = StepContext { buffers: vec![Buffer::new(1, 2, 128, 30)], .. StepContext::new() };
+        let (mut plain, mut buffered) = (Creature::new(), Creature::new());
+        genome.step(&mut plain);
+        genome.step_with(&mut buffered, &ctx);
+        assert_eq!(concnt(&plain, 1), 100);
+        // Half the rise would be soaked up, but the buffer only has room for 30.
+        assert_eq!((concnt(&buffered, 1), concnt(&buffered, 2)), (70, 30));
+        genome.step_with(&mut buffered, &ctx);
+        assert_eq!(concnt(&buffered, 1), 170);
+    }
+
+    #[test]
+    fn remapping_updates_every_reference() {
+        let genome = |offset: Id| Genome::from_genes(vec![
+            Gene::InitialState(chem(1 + offset, 10)),
+            Gene::Emitter(Emitter::new(IoType::Digital, 2 + offset, 0, 10, 0, 0, false, false)),
+            Gene::Reaction(Reaction::new(ReactionType::Normal(
+                chem(3 + offset, 1), chem(4 + offset, 1), chem(5 + offset, 1), chem(6 + offset, 1)),
+                0)),
+            Gene::Receptor(Receptor {
+                sense: ReceptorType::Coincidence(8 + offset),
+                .. Receptor::new(IoType::Digital, 7 + offset, 0, 0, 0, 0, false)
+            }),
+        ]);
+        let mapped = genome(0).map_chemicals(|id| id + 100);
+        assert_eq!(encode(&mapped).unwrap(), encode(&genome(100)).unwrap());
+    }
+
+    #[test]
+    fn reaction_cycles_find_loops_and_only_loops() {
+        let convert = |from, to| {
+            Gene::Reaction(Reaction::new(ReactionType::Fusion(chem(from, 1), chem(9, 1),
+                                                              chem(to, 1)), 0))
+        };
+        let looped = Genome::from_genes(vec![
+            Gene::InitialState(chem(1, 10)),
+            convert(1, 2),
+            convert(2, 3),
+            convert(3, 1),
+        ]);
+        assert_eq!(looped.reaction_cycles(), vec![vec![1, 2, 3]]);
+        let chain = Genome::from_genes(vec![convert(1, 2), convert(2, 3), convert(3, 4)]);
+        assert!(chain.reaction_cycles().is_empty());
+    }
+
+    #[test]
+    fn step_traces_attribute_each_change_to_its_gene() {
+        let genome = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 1, 0, 5, 0, 0, false, false)),
+            Gene::Reaction(Reaction::new(ReactionType::Fusion(chem(1, 1), chem(2, 1), chem(3, 1)),
+                                         0)),
+            Gene::InitialState(chem(4, 100)),
+        ]);
+        let mut creature = Creature::new();
+        creature.inject(1, 10);
+        creature.inject(2, 4);
+        let trace = genome.step_trace(&mut creature);
+        let expected: Vec<BTreeMap<Id, i16>> = vec![
+            vec![(1, 5)].into_iter().collect(),
+            vec![(1, -4), (2, -4), (3, 4)].into_iter().collect(),
+            BTreeMap::new(),
+        ];
+        assert_eq!(trace.deltas, expected);
+    }
+
+    #[test]
+    fn genomes_at_the_gene_cap_never_grow() {
+        let mut rng = rng(11);
+        let mut genome = Genome::from_genes((0 .. 6).map(|_| rng.gen()).collect());
+        let mut config = EvolveConfig::new();
+        config.max_genes = Some(6);
+        for _ in 0 .. 500 {
+            genome = genome.mutate_with(&mut rng, &config);
+            assert!(genome.genes.len() <= 6);
+        }
+    }
+
+    #[test]
+    fn distances_are_zero_for_equals_symmetric_and_bounded() {
+        let mut rng = rng(5);
+        let genomes: Vec<Genome> = (0 .. 6).map(|n| {
+            Genome::from_genes((0 .. n * 2).map(|_| rng.gen()).collect())
+        }).collect();
+        for a in genomes.iter() {
+            assert_eq!(Genome::distance(a, a), 0.0);
+            for b in genomes.iter() {
+                let d = Genome::distance(a, b);
+                assert!((d - Genome::distance(b, a)).abs() < 1e-4);
+                assert!(d >= 0.0 && d <= a.genes.len().max(b.genes.len()) as f32);
+            }
+        }
+        let longest = &genomes[5];
+        let shorter = Genome::from_genes(longest.genes[.. 8].to_vec());
+        let shortest = Genome::from_genes(longest.genes[.. 4].to_vec());
+        assert_eq!(Genome::distance(longest, &shorter), 2.0);
+        assert_eq!(Genome::distance(longest, &shortest), 6.0);
+    }
+
+    /// A little of everything: an initial state, emitters, reactions, a receptor and a brain.
+    fn mixed_genome() -> Genome {
+        Genome::from_genes(vec![
+            Gene::InitialState(chem(1, 120)),
+            Gene::Emitter(Emitter::new(IoType::Digital, 2, 3, 30, 7, 100, false, false)),
+            Gene::Reaction(Reaction::new(ReactionType::Fusion(chem(1, 2), chem(2, 1), chem(3, 1)),
+                                         2)),
+            Gene::Reaction(Reaction::new(ReactionType::Decay(chem(3, 1)), 5)),
+            Gene::Receptor(Receptor::new(IoType::Digital, 3, 7, 50, 100, 10, false)),
+            Gene::Brain(1, 1, vec![0.5]),
+        ])
+    }
+
+    #[test]
+    fn step_n_is_the_same_as_stepping_n_times() {
+        let (bulk, single) = (mixed_genome(), mixed_genome());
+        let (mut a, mut b) = (Creature::new(), Creature::new());
+        bulk.init(&mut a);
+        single.init(&mut b);
+        bulk.step_n(&mut a, 10);
+        for _ in 0 .. 10 {
+            single.step(&mut b);
+        }
+        assert_eq!(a.chemo_body().snapshot(), b.chemo_body().snapshot());
+        assert_eq!(a.get_locus(7), b.get_locus(7));
+        bulk.step_n(&mut a, 7);
+        single.step_n(&mut b, 7);
+        assert_eq!(a.chemo_body().snapshot(), b.chemo_body().snapshot());
+    }
+
+    #[test]
+    fn permuted_genomes_canonicalize_to_the_same_genes() {
+        let genome = mixed_genome();
+        let canonical = encode(&genome.canonicalize()).unwrap();
+        let mut rng = rng(2);
+        for _ in 0 .. 10 {
+            let mut genes = genome.genes.clone();
+            rng.shuffle(&mut genes);
+            assert_eq!(encode(&Genome::from_genes(genes).canonicalize()).unwrap(), canonical);
+        }
+    }
+
+    #[test]
+    fn crossover_refuses_incompatible_genomes() {
+        let uses = |ids: &[Id]| {
+            Genome::from_genes(ids.iter().map(|&id| Gene::InitialState(chem(id, 1))).collect())
+        };
+        let (a, b, c) = (uses(&[1, 2, 3]), uses(&[2, 3, 4]), uses(&[10, 11]));
+        assert!(a.is_compatible(&b, 0.5) && b.is_compatible(&a, 0.5));
+        assert!(!a.is_compatible(&b, 0.6));
+        assert!(!a.is_compatible(&c, 0.1));
+        assert!(Genome::new().is_compatible(&Genome::new(), 1.0));
+        let mut rng = rng(8);
+        assert!(a.crossover_compatible(&b, 0.5, &mut rng).is_some());
+        assert!(a.crossover_compatible(&c, 0.5, &mut rng).is_none());
+    }
+
+    /// Squares a chemical's concentration, scaled back into 0-255.
+    struct Square(Id);
+
+    impl Steppable for Square {
+        fn step(&self, creature: &mut Creature, _: &StepContext) {
+            let concnt = creature.chemo_body().peek(self.0) as u16;
+            creature.drain(self.0, 255);
+            creature.inject(self.0, (concnt * concnt / 255) as u8);
+        }
+    }
+
+    fn square(params: &str) -> Option<Arc<Steppable + Send + Sync>> {
+        params.parse().ok().map(|id| Arc::new(Square(id)) as Arc<Steppable + Send + Sync>)
+    }
+
+    fn custom_square(id: Id) -> CustomGene {
+        register_gene("square", square);
+        CustomGene::new("square", &id.to_string()).unwrap()
+    }
+
+    #[test]
+    fn custom_genes_step_in_turn_with_built_in_ones() {
+        let genome = Genome::from_genes(vec![
+            Gene::InitialState(chem(4, 160)),
+            Gene::Custom(custom_square(4)),
+            Gene::Emitter(Emitter::new(IoType::Digital, 4, 0, 10, 0, 0, false, false)),
+        ]);
+        let mut creature = Creature::new();
+        genome.init(&mut creature);
+        genome.step(&mut creature);
+        assert_eq!(creature.chemo_body().peek(4), 110);
+        genome.step(&mut creature);
+        assert_eq!(creature.chemo_body().peek(4), 57);
+    }
+
+    #[test]
+    fn custom_genes_load_through_the_registry() {
+        let genome = Genome::from_genes(vec![
+            Gene::InitialState(chem(4, 160)),
+            Gene::Custom(custom_square(4)),
+        ]);
+        let saved = encode(&genome).unwrap();
+        let loaded: Genome = decode(&saved).unwrap();
+        let mut creature = Creature::new();
+        loaded.init(&mut creature);
+        loaded.step(&mut creature);
+        assert_eq!(creature.chemo_body().peek(4), 100);
+        assert!(CustomGene::new("cube", "4").is_none());
+        assert!(CustomGene::new("square", "four").is_none());
+        assert!(decode::<Genome>(&saved.replace("square", "cube")).is_err());
+    }
+
+    #[test]
+    fn equilibrium_is_where_nothing_changes_any_more() {
+        // 33 fusions use up all but one of the 100 A, and leave 7 of the 40 B.
+        let genome = Genome::from_genes(vec![Gene::Reaction(Reaction::new(
+            ReactionType::Fusion(chem(1, 3), chem(2, 1), chem(3, 1)), 0))]);
+        let start: HashMap<Id, Concentration> = vec![(1, 100), (2, 40)].into_iter().collect();
+        let settled = genome.equilibrium(&start, 0.5, 10).unwrap();
+        assert_eq!((settled[&1], settled[&2], settled[&3]), (1, 7, 33));
+        assert!(genome.equilibrium(&start, 0.5, 1).is_none());
+    }
+
+    #[test]
+    fn stoichiometry_gives_net_coefficients_times_frequency() {
+        let genome = Genome::from_genes(vec![
+            Gene::InitialState(chem(9, 10)),
+            Gene::Reaction(Reaction::new(ReactionType::Fusion(chem(1, 2), chem(2, 1), chem(3, 1)),
+                                         2)),
+            Gene::Reaction(Reaction::new(ReactionType::Decay(chem(3, 1)), 0)),
+        ]);
+        let (ids, matrix) = genome.stoichiometry();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(matrix, vec![vec![-1.0, 0.0], vec![-0.5, 0.0], vec![0.5, -1.0]]);
+    }
+
+    #[test]
+    fn shared_reactants_are_split_in_proportion_to_demand() {
+        let fuse = |with, into| {
+            Gene::Reaction(Reaction::new(ReactionType::Fusion(chem(1, 1), chem(with, 1),
+                                                              chem(into, 1)), 0))
+        };
+        let genome = Genome::from_genes(vec![fuse(5, 2), fuse(6, 3)]);
+        let run = |share: bool| {
+            let ctx = StepContext { share_reactants: share, .. StepContext::new() };
+            let mut creature = Creature::new();
+            creature.inject(1, 30);
+            creature.inject(5, 100);
+            creature.inject(6, 100);
+            genome.clone().step_with(&mut creature, &ctx);
+            let body = creature.chemo_body();
+            (body.peek(1), body.peek(2), body.peek(3))
+        };
+        assert_eq!(run(false), (0, 30, 0));
+        assert_eq!(run(true), (0, 15, 15));
+    }
+
+    #[test]
+    fn defaults_match_new_and_run_a_simulation() {
+        let genome = mixed_genome();
+        let (mut creature, mut expected) = (Creature::new(), Creature::new());
+        genome.init(&mut creature);
+        genome.init(&mut expected);
+        genome.step_n_with(&mut creature, &StepContext::default(), 20);
+        genome.step_n_with(&mut expected, &StepContext::new(), 20);
+        assert_eq!(creature.concentrations(), expected.concentrations());
+        let config = EvolveConfig::default();
+        assert!(config.max_genes.is_none() && config.sharing.is_none());
+        assert_eq!(encode(&genome.clone().mutate_with(&mut rng(1), &config)).unwrap(),
+                   encode(&genome.clone().mutate_with(&mut rng(1), &EvolveConfig::new())).unwrap());
+        assert!(EvolutionLog::default().generations.is_empty());
+        assert_eq!(AgeSchedule::default().0, [0; 7]);
+    }
+
+    #[test]
+    fn builders_hand_back_every_validation_error() {
+        let built = GenomeBuilder::new()
+            .initial_state(chem(1, 10))
+            .emitter(Emitter::new(IoType::Digital, 1, 0, 5, 0, 0, false, false))
+            .reaction(Reaction::new(ReactionType::Decay(chem(1, 1)), 2))
+            .receptor(Receptor::new(IoType::Analogue, 1, 3, 0, 10, 5, false))
+            .build();
+        assert_eq!(built.ok().map(|genome| genome.genes.len()), Some(4));
+        let errors = GenomeBuilder::new()
+            .reaction(Reaction::new(ReactionType::Decay(chem(1, 0)), 0))
+            .initial_state(chem(2, 10))
+            .receptor(Receptor {
+                sense: ReceptorType::Linear(vec![(2, 1.0), (3, f32::NAN)]),
+                .. Receptor::new(IoType::Analogue, 2, 1, 0, 10, 0, false)
+            })
+            .build().err().unwrap();
+        assert_eq!(errors, vec![ValidationError::ZeroCoefficient(0, 1),
+                                ValidationError::NonFiniteWeight(2, 3)]);
+    }
+
+    #[test]
+    fn every_kind_of_gene_round_trips_through_json_on_its_own() {
+        let stochastic = Reaction {
+            stochastic: true, .. Reaction::new(ReactionType::Fusion(chem(1, 2), chem(2, 1),
+                                                                    chem(3, 1)), 4)
+        };
+        let genes = vec![
+            Gene::InitialState(chem(1, 120)),
+            Gene::Emitter(Emitter::new(IoType::Analogue, 2, 3, 30, 7, 100, true, false)),
+            Gene::Reaction(stochastic),
+            Gene::Receptor(Receptor::new(IoType::Digital, 3, 7, 50, 100, 10, true)),
+            Gene::Brain(2, 1, vec![0.5, -0.25]),
+            Gene::Custom(custom_square(4)),
+        ];
+        for gene in genes.iter() {
+            let json = gene.to_json().unwrap();
+            assert_eq!(Gene::from_json(&json).unwrap().to_json().unwrap(), json);
+        }
+        assert!(Gene::from_json(r#"{"variant":"Nothing","fields":[]}"#).is_err());
+    }
+
+    #[test]
+    fn throughput_adds_up_what_reactions_use_and_resets_to_zero() {
+        let genome = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 1, 0, 4, 0, 0, false, false)),
+            Gene::Emitter(Emitter::new(IoType::Digital, 2, 0, 6, 0, 0, false, false)),
+            Gene::Reaction(Reaction::new(ReactionType::Decay(chem(1, 1)), 0)),
+            Gene::Reaction(Reaction::new(ReactionType::Decay(chem(2, 2)), 0)),
+        ]);
+        let throughputs = |genome: &Genome| match (&genome.genes[2], &genome.genes[3]) {
+            (&Gene::Reaction(ref a), &Gene::Reaction(ref b)) => (a.throughput(), b.throughput()),
+            _ => unreachable!(),
+        };
+        let mut creature = Creature::new();
+        genome.step_n(&mut creature, 5);
+        assert_eq!(throughputs(&genome), (20.0, 30.0));
+        let loaded: Genome = decode(&encode(&genome).unwrap()).unwrap();
+        assert_eq!(throughputs(&loaded), (0.0, 0.0));
+        genome.reset_throughput();
+        assert_eq!(throughputs(&genome), (0.0, 0.0));
+        genome.step(&mut creature);
+        assert_eq!(throughputs(&genome), (4.0, 6.0));
+    }
+
+    #[test]
+    fn seeded_noise_is_reproducible_and_stays_within_its_amplitude() {
+        let genome = Genome::from_genes(vec![]);
+        let run = |seed| {
+            let ctx = StepContext { noise: Some(5), .. StepContext::with_seed(seed) };
+            let mut creature = Creature::new();
+            for &(id, concnt) in [(1, 0), (2, 128), (3, 255)].iter() {
+                creature.inject(id, concnt);
+            }
+            creature.ensure_chemicals(&[1]);
+            let mut history = vec![creature.concentrations()];
+            for _ in 0 .. 100 {
+                genome.step_with(&mut creature, &ctx);
+                history.push(creature.concentrations());
+            }
+            history
+        };
+        let history = run(9);
+        assert!(history == run(9));
+        assert!(history != run(10));
+        for pair in history.windows(2) {
+            for id in 1 .. 4 {
+                assert!((pair[1][&id] as i16 - pair[0][&id] as i16).abs() <= 5);
+            }
+        }
+        let mut creature = Creature::new();
+        creature.inject(2, 128);
+        genome.step_with(&mut creature, &StepContext { noise: Some(5), .. StepContext::new() });
+        assert_eq!(creature.chemo_body().peek(2), 128);
+    }
+
+    #[test]
+    fn counts_each_gene_referring_to_a_chemical_once() {
+        let genome = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 4, 0, 10, 0, 0, false, false)),
+            Gene::Reaction(Reaction::new(ReactionType::Catalytic(chem(4, 1), chem(4, 1),
+                                                                 chem(5, 1)), 0)),
+            Gene::Reaction(Reaction::new(ReactionType::Decay(chem(5, 1)), 0)),
+            Gene::Receptor(Receptor::new(IoType::Digital, 4, 7, 0, 255, 10, false)),
+        ]);
+        assert_eq!(genome.count_references(4), 3);
+        assert_eq!(genome.count_references(5), 2);
+        assert_eq!(genome.count_references(6), 0);
+    }
+
+    #[test]
+    fn replayed_receptor_firings_match_the_run() {
+        let genome = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 1, 0, 10, 0, 0, false, false)),
+            Gene::Reaction(Reaction::new(ReactionType::Decay(chem(1, 1)), 5)),
+            Gene::Receptor(Receptor::new(IoType::Digital, 1, 7, 0, 100, 25, false)),
+            Gene::Receptor(Receptor::new(IoType::Analogue, 1, 8, 50, 255, 15, false)),
+        ]);
+        let mut creature = Creature::new();
+        let (mut history, mut live) = (Vec::new(), Vec::new());
+        for tick in 0 .. 30 {
+            genome.step(&mut creature);
+            history.push(creature.concentrations());
+            if creature.get_locus(7) == 100 {
+                live.push(ReceptorFiring { tick: tick, gene: 2, output: 100 });
+            }
+            if creature.get_locus(8) > 50 {
+                live.push(ReceptorFiring { tick: tick, gene: 3, output: creature.get_locus(8) });
+            }
+        }
+        // The chemical climbs 10, 20, 30, 40 and is then decayed away, over and over.
+        assert_eq!(live.len(), 6 * (2 + 3));
+        assert_eq!(genome.replay_history(&history), live);
+    }
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn genomes_step_on_other_threads_with_a_clone_and_creature_each() {
+        assert_send::<Genome>();
+        assert_send::<Creature>();
+        assert_send::<StepContext>();
+        assert_send::<Organism>();
+        let genome = mixed_genome();
+        let mut expected = Creature::new();
+        genome.init(&mut expected);
+        genome.step_n(&mut expected, 60);
+        let threads: Vec<_> = (0 .. 4).map(|_| {
+            let genome = genome.clone();
+            thread::spawn(move || {
+                let mut creature = Creature::new();
+                genome.init(&mut creature);
+                genome.step_n(&mut creature, 60);
+                creature.concentrations()
+            })
+        }).collect();
+        for handle in threads {
+            assert_eq!(handle.join().unwrap(), expected.concentrations());
+        }
+    }
+
+    #[test]
+    fn decay_rates_shrink_chemicals_geometrically_every_step() {
+        let genome = Genome::from_genes(vec![]);
+        let decaying = |dt| StepContext {
+            dt: dt, decay_rates: vec![(1, 0.5)].into_iter().collect(), .. StepContext::new()
+        };
+        let mut creature = Creature::new();
+        creature.inject(1, 200);
+        creature.inject(2, 200);
+        let mut levels = Vec::new();
+        for _ in 0 .. 8 {
+            genome.step_with(&mut creature, &decaying(1.0));
+            levels.push(creature.chemo_body().peek(1));
+        }
+        assert_eq!(levels, vec![100, 50, 25, 12, 6, 3, 1, 0]);
+        assert_eq!(creature.chemo_body().peek(2), 200);
+        creature.inject(1, 200);
+        genome.step_with(&mut creature, &decaying(2.0));
+        assert_eq!(creature.chemo_body().peek(1), 50);
+    }
+
+    #[test]
+    fn quiescent_genomes_change_nothing_from_the_given_state() {
+        let state: HashMap<Id, Concentration> = vec![(1, 0), (2, 40)].into_iter().collect();
+        let dead = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 2, 0, 0, 0, 0, false, false)),
+            Gene::Reaction(Reaction::new(ReactionType::Fusion(chem(1, 1), chem(2, 1),
+                                                              chem(3, 1)), 0)),
+        ]);
+        assert!(dead.is_quiescent(&state));
+        let slow = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 2, 12, 5, 0, 0, false, false)),
+        ]);
+        assert!(!slow.is_quiescent(&state));
+        let decaying = Genome::from_genes(vec![
+            Gene::Reaction(Reaction::new(ReactionType::Decay(chem(2, 1)), 0)),
+        ]);
+        assert!(!decaying.is_quiescent(&state));
+    }
+
+    #[test]
+    fn approximately_equal_genomes_differ_only_in_weights_within_epsilon() {
+        let genome = |weight: f32, term: f32, gain: Concentration| Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 2, 3, gain, 7, 100, false, false)),
+            Gene::Brain(1, 1, vec![weight]),
+            Gene::Receptor(Receptor {
+                sense: ReceptorType::Linear(vec![(1, term), (2, -0.5)]),
+                .. Receptor::new(IoType::Analogue, 1, 4, 0, 255, 0, false)
+            }),
+        ]);
+        let base = genome(0.5, 1.5, 30);
+        assert!(base.approx_eq(&genome(0.5, 1.5, 30), 0.0));
+        assert!(base.approx_eq(&genome(0.5005, 1.4995, 30), 0.001));
+        assert!(!base.approx_eq(&genome(0.52, 1.5, 30), 0.001));
+        assert!(!base.approx_eq(&genome(0.5, 1.51, 30), 0.001));
+        assert!(!base.approx_eq(&genome(0.5, 1.5, 31), 0.001));
+        assert!(!base.approx_eq(&Genome::from_genes(base.genes[.. 2].to_vec()), 0.001));
+    }
+
+    #[test]
+    fn checked_steps_report_whether_anything_changed() {
+        let genome = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 1, 0, 10, 0, 0, false, false)),
+        ]);
+        let mut creature = Creature::new();
+        let changes: Vec<bool> = (0 .. 27).map(|_| genome.step_checked(&mut creature)).collect();
+        assert!(changes[.. 26].iter().all(|&changed| changed));
+        assert!(!changes[26]);
+        let inert = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 2, 0, 0, 0, 0, false, false)),
+            Gene::Reaction(Reaction::new(ReactionType::Decay(chem(3, 1)), 0)),
+        ]);
+        assert!(!inert.step_checked(&mut creature));
+    }
+
+    #[test]
+    fn dot_output_has_a_node_for_each_chemical_and_gene_and_an_edge_for_each_link() {
+        let genome = Genome::from_genes(vec![
+            Gene::InitialState(chem(1, 120)),
+            Gene::Emitter(Emitter::new(IoType::Digital, 2, 3, 30, 7, 100, false, false)),
+            Gene::Reaction(Reaction::new(ReactionType::Catalytic(chem(4, 1), chem(1, 1),
+                                                                 chem(3, 1)), 2)),
+            Gene::Receptor(Receptor::new(IoType::Digital, 3, 7, 50, 100, 10, false)),
+        ]);
+        let names = vec![(4, "enzyme".to_owned())].into_iter().collect();
+        let dot = genome.to_dot_named(&names);
+        assert!(dot.starts_with("digraph genome {\n") && dot.ends_with("}\n"));
+        for line in &[
+            "    chem1 [label=\"1\"];",
+            "    chem4 [label=\"enzyme\"];",
+            "    gene1 [label=\"emitter 1\", shape=invhouse];",
+            "    gene1 -> chem2;",
+            "    gene2 [label=\"reaction 2\", shape=box];",
+            "    chem4 -> gene2 [style=dashed];",
+            "    chem1 -> gene2;",
+            "    gene2 -> chem3;",
+            "    gene3 [label=\"receptor 3\", shape=house];",
+            "    chem3 -> gene3;",
+        ] {
+            assert!(dot.lines().any(|l| l == *line), "{} is missing from:\n{}", line, dot);
+        }
+        assert!(!dot.contains("gene0"));
+        assert_eq!(dot.lines().filter(|l| l.contains("->")).count(), 5);
+        assert_eq!(genome.to_dot(), dot.replace("enzyme", "4"));
+    }
+
+    #[test]
+    fn complexity_weighs_genes_by_kind_and_grows_with_the_genome() {
+        let genome = mixed_genome();
+        assert!((genome.complexity() - 7.6).abs() < 1e-5, "{}", genome.complexity());
+        let mut larger = genome.clone();
+        larger.genes.push(Gene::Reaction(Reaction::new(ReactionType::Decay(chem(2, 1)), 3)));
+        larger.genes.push(Gene::Brain(1, 3, vec![0.1, 0.2, 0.3]));
+        assert!((larger.complexity() - genome.complexity() - 3.3).abs() < 1e-5);
+        assert_eq!(Genome::from_genes(vec![]).complexity(), 0.0);
+        let emitter = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 1, 0, 1, 0, 0, false, false)),
+        ]);
+        let reaction = Genome::from_genes(vec![
+            Gene::Reaction(Reaction::new(ReactionType::Decay(chem(1, 1)), 0)),
+        ]);
+        assert!(reaction.complexity() > emitter.complexity());
+    }
+
+    #[test]
+    fn retain_drops_the_genes_it_is_told_to_in_place() {
+        let is_emitter = |gene: &Gene| match *gene { Gene::Emitter(_) => true, _ => false };
+        let mut genome = mixed_genome();
+        let emitter = Emitter::new(IoType::Digital, 1, 0, 5, 0, 0, false, false);
+        genome.genes.push(Gene::Emitter(emitter));
+        genome.retain(|gene| !is_emitter(gene));
+        assert!(!genome.genes.iter().any(is_emitter));
+        let mut expected = mixed_genome();
+        expected.genes.remove(1);
+        assert!(genome.approx_eq(&expected, 0.0));
+    }
+
+    #[test]
+    fn deltas_come_out_in_id_order_and_runs_repeat_byte_for_byte() {
+        let normal = ReactionType::Normal(chem(9, 1), chem(3, 1), chem(7, 2), chem(1, 1));
+        let mut body = ChemoBody::new();
+        body.gain(9, 10);
+        body.gain(3, 10);
+        let deltas = Reaction::new(normal, 0).simulate_once(&body);
+        assert_eq!(deltas.keys().cloned().collect::<Vec<_>>(), vec![1, 3, 7, 9]);
+        let run = || {
+            let genome = Genome::from_genes(vec![
+                Gene::InitialState(chem(9, 200)),
+                Gene::InitialState(chem(3, 250)),
+                Gene::Emitter(Emitter::new(IoType::Digital, 7, 0, 90, 0, 0, false, false)),
+                Gene::Reaction(Reaction::new(normal, 0)),
+                Gene::Reaction(Reaction::new(ReactionType::Fusion(chem(7, 3), chem(3, 1),
+                                                                  chem(9, 2)), 0)),
+            ]);
+            let ctx = StepContext { share_reactants: true, .. StepContext::new() };
+            let mut creature = Creature::new();
+            genome.init(&mut creature);
+            let mut steps = Vec::new();
+            for _ in 0 .. 20 {
+                genome.step_with(&mut creature, &ctx);
+                let ordered: BTreeMap<Id, Concentration> =
+                    creature.concentrations().into_iter().collect();
+                steps.push(encode(&ordered).unwrap());
+            }
+            steps.concat().into_bytes()
+        };
+        let first = run();
+        for _ in 0 .. 50 {
+            assert!(run() == first);
+        }
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn each_chemical_is_clamped_at_its_own_cap_in_release_builds() {
+        let genome = Genome::from_genes((1 .. 4).map(|id| {
+            Gene::Emitter(Emitter::new(IoType::Digital, id, 0, 100, 0, 0, false, false))
+        }).collect());
+        let ctx = StepContext {
+            caps: vec![(1, 77), (2, 150)].into_iter().collect(), .. StepContext::new()
+        };
+        let mut creature = Creature::new();
+        genome.step_n_with(&mut creature, &ctx, 3);
+        let body = creature.chemo_body();
+        assert_eq!((body.peek(1), body.peek(2), body.peek(3)), (77, 150, 255));
+    }
+
+    #[test]
+    fn phenotypes_are_reproducible_for_a_fixed_stimulus() {
+        let genome = mixed_genome();
+        let stimulus = [(2, 60), (5, 255)];
+        let phenotype = genome.phenotype(&stimulus, 40);
+        assert_eq!(phenotype.len(), 256);
+        assert!(phenotype.iter().all(|&level| level >= 0.0 && level <= 1.0));
+        assert_eq!(phenotype[5], 1.0);
+        assert_eq!(genome.phenotype(&stimulus, 40), phenotype);
+        assert!(genome.clone().phenotype(&stimulus, 40) == phenotype);
+        assert!(genome.phenotype(&[(2, 60)], 40) != phenotype);
+    }
+
+    #[test]
+    fn populations_round_trip_through_a_buffer() {
+        let population = vec![
+            mixed_genome(),
+            Genome::from_genes(vec![]),
+            Genome::from_genes(vec![Gene::Brain(2, 2, vec![0.5, -1.0, 0.25, 2.0])]),
+        ];
+        let mut buffer = Vec::new();
+        write_population(&population, &mut buffer).unwrap();
+        assert_eq!(buffer.iter().filter(|&&byte| byte == b'\n').count(), population.len());
+        let read = read_population(Cursor::new(&buffer)).unwrap();
+        assert_eq!(read.len(), population.len());
+        assert!(read.iter().zip(population.iter()).all(|(a, b)| a.approx_eq(b, 0.0)));
+        assert!(read_population(Cursor::new(b"not a genome\n")).is_err());
+        assert!(read_population(Cursor::new(b"")).unwrap().is_empty());
+    }
+
+    thread_local! {
+        static LOGGED: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    }
+
+    /// Keeps what is logged on each thread for that thread's tests to look at.
+    struct Capture;
+
+    impl Log for Capture {
+        fn enabled(&self, _: &LogMetadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &LogRecord) {
+            LOGGED.with(|logged| logged.borrow_mut().push(record.args().to_string()));
+        }
+    }
+
+    #[test]
+    fn mutations_are_logged() {
+        log::set_logger(|max_level| {
+            max_level.set(LogLevelFilter::Debug);
+            Box::new(Capture)
+        }).unwrap();
+        Genome::from_genes(vec![Gene::InitialState(chem(1, 10))])
+            .mutate_with(&mut rng(3), &EvolveConfig::new());
+        let logged = LOGGED.with(|logged| logged.borrow().clone());
+        assert!(logged.iter().any(|line| line.starts_with("Mutation ")), "{:?}", logged);
+    }
+
+    #[test]
+    fn spliced_modules_are_appended_with_their_ids_offset() {
+        let module = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 2, 3, 30, 7, 100, false, false)),
+            Gene::Reaction(Reaction::new(ReactionType::Fusion(chem(1, 2), chem(2, 1), chem(3, 1)),
+                                         2)),
+        ]);
+        let mut genome = Genome::from_genes(vec![Gene::InitialState(chem(1, 120))]);
+        genome.splice_module(&module, 100).unwrap();
+        assert_eq!(genome.genes.len(), 3);
+        match genome.genes[1] {
+            Gene::Emitter(ref e) => assert_eq!(e.chemical, 102),
+            _ => panic!("The module's emitter is not after the genome's own gene."),
+        }
+        assert_eq!(genome.referenced_chemicals().into_iter().collect::<Vec<_>>(),
+                   vec![1, 101, 102, 103]);
+        match genome.genes[2] {
+            Gene::Reaction(ref r) => {
+                let coefficients: Vec<_> = r.kind.chemicals().iter().map(|c| c.concnt()).collect();
+                assert_eq!(coefficients, vec![2, 1, 1]);
+            },
+            _ => panic!("The module's reaction is not last."),
+        }
+        assert_eq!(genome.splice_module(&module, 253), Err(3));
+        assert_eq!(genome.genes.len(), 3);
+        assert_eq!(genome.splice_module(&module, 252), Ok(()));
+        assert_eq!(genome.referenced_chemicals().into_iter().last(), Some(255));
+    }
+
+    #[test]
+    fn genes_collect_into_a_genome() {
+        let genome = mixed_genome();
+        let reactions: Genome = genome.genes.iter().filter(|gene| match **gene {
+            Gene::Reaction(_) => true,
+            _ => false,
+        }).cloned().collect();
+        assert_eq!(reactions.genes.len(), 2);
+        assert!(reactions.approx_eq(&Genome::from_genes(genome.genes[2 .. 4].to_vec()), 0.0));
+        let empty: Genome = Vec::<Gene>::new().into_iter().collect();
+        assert!(empty.genes.is_empty());
+    }
+
+    #[test]
+    fn loading_turns_away_nan_parameters() {
+        let path = temp_path("nan");
+        File::create(&path).unwrap().write_all(br#"{"genes":[{"variant":"Homeostat","fields":[
+            {"chemical":1,"target":null,"rate":0.5}]}]}"#).unwrap();
+        let loaded = Genome::load(&path);
+        fs::remove_file(&path).unwrap();
+        let e = loaded.err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        assert!(e.to_string().contains("gene 0 has a parameter that is infinity or NaN"), "{}", e);
+        let genome = Genome::from_genes(vec![Gene::Brain(1, 1, vec![f32::INFINITY])]);
+        assert_eq!(genome.validate(), vec![ValidationError::NonFiniteParameter(0)]);
+    }
+
+    #[test]
+    fn isolated_genes_report_only_their_own_changes() {
+        let state: HashMap<Id, Concentration> = vec![(1, 100), (2, 30)].into_iter().collect();
+        let deltas = |changes: &[(Id, i16)]| changes.iter().cloned().collect::<DeltaMap>();
+        let emitter = Gene::Emitter(Emitter::new(IoType::Digital, 2, 0, 25, 0, 0, false, false));
+        assert_eq!(emitter.step_isolated(&state), deltas(&[(2, 25)]));
+        let reaction = Gene::Reaction(Reaction::new(ReactionType::Fusion(chem(1, 2), chem(2, 1),
+                                                                          chem(3, 1)), 0));
+        assert_eq!(reaction.step_isolated(&state),
+                   deltas(&[(1, -60), (2, -30), (3, 30)]));
+        let slow = Gene::Emitter(Emitter::new(IoType::Digital, 2, 5, 25, 0, 0, false, false));
+        assert!(slow.step_isolated(&state).is_empty());
+        let receptor = Gene::Receptor(Receptor::new(IoType::Digital, 1, 7, 0, 255, 10, false));
+        assert!(receptor.step_isolated(&state).is_empty());
+        assert_eq!(emitter.step_isolated(&state), deltas(&[(2, 25)]));
+    }
+
+    #[test]
+    fn runaway_genomes_pin_a_chemical_at_the_top() {
+        let emitter = |gain| {
+            Gene::Emitter(Emitter::new(IoType::Digital, 1, 0, gain, 0, 0, false, false))
+        };
+        let start: HashMap<Id, Concentration> = vec![(2, 40)].into_iter().collect();
+        let strong = Genome::from_genes(vec![emitter(100)]);
+        assert!(strong.is_runaway(&start, 30, 0.5));
+        let balanced = Genome::from_genes(vec![
+            emitter(10), Gene::Reaction(Reaction::new(ReactionType::Decay(chem(1, 1)), 0)),
+        ]);
+        assert!(!balanced.is_runaway(&start, 30, 0.5));
+        let flushed = Genome::from_genes(vec![
+            emitter(100), Gene::Reaction(Reaction::new(ReactionType::Decay(chem(1, 1)), 5)),
+        ]);
+        assert!(!flushed.is_runaway(&start, 30, 0.5));
+        assert!(flushed.is_runaway(&start, 4, 0.5));
+    }
+
+    #[test]
+    fn saving_refuses_to_overwrite_a_file_modified_since() {
+        let path = temp_path("modified-since");
+        let genome = mixed_genome();
+        let written = genome.save_unless_modified(&path, SystemTime::now()).unwrap();
+        genome.save_unless_modified(&path, written).unwrap();
+        File::create(&path).unwrap().write_all(b"edited elsewhere").unwrap();
+        let edited = fs::metadata(&path).unwrap().modified().unwrap();
+        let e = genome.save_unless_modified(&path, edited - Duration::from_secs(1)).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::Other);
+        let mut contents = String::new();
+        File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "edited elsewhere");
+        genome.save_unless_modified(&path, edited).unwrap();
+        assert!(Genome::load(&path).unwrap().approx_eq(&genome, 0.0));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn views_pick_out_each_kind_of_gene_in_order() {
+        let mut genome = mixed_genome();
+        genome.genes.push(Gene::ReactionGroup(ReactionGroup::new(vec![
+            ReactionType::Decay(chem(2, 1)),
+        ], 1)));
+        genome.genes.push(Gene::Emitter(Emitter::new(IoType::Analogue, 4, 0, 9, 7, 0, true, true)));
+        let emitters: Vec<_> = genome.emitters().iter().map(|e| (e.chemical, e.gain)).collect();
+        assert_eq!(emitters, vec![(2, 30), (4, 9)]);
+        let reactions: Vec<_> = genome.reactions().iter().map(|r| r.rate).collect();
+        assert_eq!(reactions, vec![2, 5]);
+        let receptors: Vec<_> = genome.receptors().iter().map(|r| (r.chemical, r.locus)).collect();
+        assert_eq!(receptors, vec![(3, 7)]);
+        assert!(Genome::from_genes(vec![]).emitters().is_empty());
+    }
+
+    #[test]
+    fn genomes_saved_mid_cycle_fire_on_the_same_steps_after_loading() {
+        let genome = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 1, 5, 10, 0, 0, false, false)),
+            Gene::Reaction(Reaction::new(ReactionType::Decay(chem(1, 1)), 7)),
+        ]);
+        let mut creature = Creature::new();
+        genome.step_n(&mut creature, 3);
+        let path = temp_path("mid-cycle");
+        genome.save(&path).unwrap();
+        let loaded = Genome::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut resumed = creature_with(&creature.concentrations());
+        let mut levels = Vec::new();
+        for _ in 0 .. 20 {
+            genome.step(&mut creature);
+            loaded.step(&mut resumed);
+            assert_eq!(resumed.concentrations(), creature.concentrations());
+            levels.push(creature.chemo_body().peek(1));
+        }
+        assert_eq!(&levels[.. 8], &[0, 10, 10, 0, 0, 0, 10, 10]);
+    }
+
+    #[test]
+    fn decoding_errors_keep_where_they_were_found() {
+        let json = "{\"genes\":[\n{\"variant\" \"Decay\"}]}";
+        match Genome::try_from_json(json) {
+            Err(DecoderError::ParseError(ParserError::SyntaxError(code, line, column))) => {
+                assert_eq!(code, ErrorCode::ExpectedColon);
+                assert_eq!((line, column), (2, 13));
+            }
+            other => panic!("Expected a syntax error, got {:?}", other.map(|_| ())),
+        }
+        let path = temp_path("malformed");
+        File::create(&path).unwrap().write_all(json.as_bytes()).unwrap();
+        let loaded = Genome::load(&path);
+        fs::remove_file(&path).unwrap();
+        match loaded {
+            Err(e) => assert!(e.to_string().contains("2, 13")),
+            Ok(_) => panic!("Expected a malformed genome to be turned away"),
+        }
+    }
+
+    #[test]
+    fn dense_steps_match_plain_steps() {
+        // Each creature gets its own copy, as genes keep count of their own ticks.
+        let (plain_genome, dense_genome) = (mixed_genome(), mixed_genome());
+        let (mut plain, mut dense) = (Creature::new(), Creature::new());
+        let mut deltas = DeltaBuffer::new();
+        for _ in 0 .. 50 {
+            plain_genome.step(&mut plain);
+            dense_genome.step_dense(&mut dense, &mut deltas);
+            assert_eq!(plain.concentrations(), dense.concentrations());
+        }
+    }
+
+    #[test]
+    fn reachability_follows_reactions_whose_inputs_can_all_be_had() {
+        let genome = Genome::from_genes(vec![
+            Gene::Reaction(Reaction::new(ReactionType::Fusion(chem(2, 1), chem(4, 1), chem(3, 1)),
+                                         0)),
+            Gene::Reaction(Reaction::new(ReactionType::Decay(chem(9, 1)), 0)),
+            Gene::Reaction(Reaction::new(ReactionType::Split(chem(1, 1), chem(2, 1), chem(5, 1)),
+                                         0)),
+            Gene::Reaction(Reaction::new(ReactionType::Catalytic(chem(6, 1), chem(2, 1),
+                                                                 chem(7, 1)), 0)),
+            Gene::Exchange(Exchange::new(5, 4, 0.5)),
+        ]);
+        let reachable = |sources: &[Id]| -> Vec<Id> {
+            genome.reachable_from(sources).into_iter().collect()
+        };
+        assert_eq!(reachable(&[1]), vec![1, 2, 3, 4, 5]);
+        assert_eq!(reachable(&[1, 6]), vec![1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(reachable(&[2]), vec![2]);
+        assert_eq!(reachable(&[]), Vec::<Id>::new());
+    }
+
+    #[test]
+    fn mutate_n_reports_one_reproducible_event_for_each_mutation() {
+        let config = EvolveConfig::new();
+        let run = |seed| {
+            let mut genome = mixed_genome();
+            let events = genome.mutate_n(10, &mut rng(seed), &config);
+            (genome, events)
+        };
+        let (genome, events) = run(8);
+        assert_eq!(events.len(), 10);
+        assert_eq!(encode(&events).unwrap(), encode(&run(8).1).unwrap());
+        let mut replayed = mixed_genome();
+        for event in events.iter() {
+            replayed.apply_mutation(event);
+        }
+        assert!(replayed.approx_eq(&genome, 0.0));
+        let mut full = EvolveConfig::new();
+        full.max_genes = Some(0);
+        assert!(Genome::new().mutate_n(10, &mut rng(8), &full).is_empty());
+    }
+
+    #[test]
+    fn mutating_a_brain_nudges_one_weight() {
+        let mut config = EvolveConfig::new();
+        config.max_genes = Some(2);
+        let mut nudged = 0;
+        for seed in 0 .. 100 {
+            let mut genome = Genome::from_genes(vec![
+                Gene::InitialState(chem(1, 120)),
+                Gene::Brain(2, 1, vec![0.5, -0.25]),
+            ]);
+            match genome.mutate_once(&mut rng(seed), &config) {
+                Some(MutationEvent { index: 1, after: Some(Gene::Brain(2, 1, weights)), .. }) => {
+                    assert_eq!(weights.iter().zip([0.5, -0.25].iter())
+                                      .filter(|&(a, b)| a != b).count(), 1);
+                    nudged += 1;
+                },
+                _ => {},
+            }
+            let mut empty = Genome::from_genes(vec![
+                Gene::InitialState(chem(1, 120)),
+                Gene::Brain(0, 0, vec![]),
+            ]);
+            match empty.mutate_once(&mut rng(seed), &config) {
+                Some(MutationEvent { index: 1, after: Some(_), .. }) =>
+                    panic!("A brain without weights has nothing to nudge"),
+                _ => {},
+            }
+        }
+        assert!(nudged > 0);
+    }
+
+    #[test]
+    fn frozen_genes_come_through_mutation_and_crossover_byte_for_byte() {
+        let mut stuck = EvolveConfig::new();
+        stuck.max_genes = Some(2);
+        stuck.frozen = vec![0, 1].into_iter().collect();
+        let mut all_frozen = Genome::from_genes(mixed_genome().genes[.. 2].to_vec());
+        assert!(all_frozen.mutate_n(10, &mut rng(8), &stuck).is_empty());
+        let mut config = EvolveConfig::new();
+        config.frozen = vec![0, 2].into_iter().collect();
+        let original = mixed_genome();
+        let frozen = |genome: &Genome| {
+            (encode(&genome.genes[0]).unwrap(), encode(&genome.genes[2]).unwrap())
+        };
+        let mut rng = rng(12);
+        let mut genome = original.clone();
+        for _ in 0 .. 50 {
+            genome.mutate_n(5, &mut rng, &config);
+            assert_eq!(frozen(&genome), frozen(&original));
+            let other = Genome::from_genes((0 .. 6).map(|_| rng.gen()).collect());
+            genome = genome.crossover_frozen(&other, &config.frozen, &mut rng);
+            assert_eq!(frozen(&genome), frozen(&original));
+        }
+    }
+
+    /// A genome written before reactions and receptors had any of their optional settings.
+    const BASELINE_JSON: &'static str = r#"{"genes":[
+        {"variant":"InitialState","fields":[{"id":1,"concentration":50}]},
+        {"variant":"Emitter","fields":[{"kind":"Digital","chemical":2,"rate":3,"gain":4,"locus":5,
+            "threshold":6,"clear_after_read":false,"invert":false,"tick":{"_field0":1}}]},
+        {"variant":"Reaction","fields":[{"kind":{"variant":"Decay","fields":[
+            {"id":1,"concentration":2}]},"rate":3,"tick":{"_field0":2}}]},
+        {"variant":"Receptor","fields":[{"kind":"Analogue","chemical":1,"locus":7,"nominal":8,
+            "gain":9,"threshold":10,"invert":true}]}
+    ]}"#;
+
+    #[test]
+    fn loads_genomes_saved_before_optional_settings() {
+        let genome = Genome::try_from_json(BASELINE_JSON).unwrap();
+        assert_eq!(genome.genes.len(), 4);
+        assert!(genome.emitters()[0].schedule.is_none());
+        let reaction = genome.reactions()[0];
+        assert!(!reaction.stochastic && !reaction.catalyst_scaled);
+        assert!(match reaction.kinetics { Kinetics::LimitingReagent => true, _ => false });
+        assert!(reaction.fires(1.0));
+        let receptor = genome.receptors()[0];
+        assert!(match receptor.sense { ReceptorType::Single => true, _ => false });
+        assert!(!receptor.edge && receptor.relative_to.is_none());
+        assert_eq!((receptor.last.get(), receptor.refractory, receptor.resting.get()), (0, 0, 0));
+        assert_eq!((receptor.chemical, receptor.locus, receptor.threshold), (1, 7, 10));
+    }
+
+    #[test]
+    fn ignores_fields_it_does_not_know() {
+        let json = r#"{"version":2,"genes":[{"variant":"Reaction","fields":[{"kind":{
+            "variant":"Decay","fields":[{"id":1,"concentration":2}]},"rate":3,
+            "tick":{"_field0":0},"colour":"red"}]}]}"#;
+        let genome = Genome::try_from_json(json).unwrap();
+        assert_eq!(genome.reactions()[0].rate, 3);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Chemical 1 reached 50, over its cap of 10.")]
+    fn going_over_a_cap_panics_in_debug_builds() {
+        let genome = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 1, 0, 50, 0, 0, false, false)),
+        ]);
+        let ctx = StepContext { caps: vec![(1, 10)].into_iter().collect(), .. StepContext::new() };
+        genome.step_with(&mut Creature::new(), &ctx);
+    }
+
+    #[test]
+    fn zero_coefficients_never_run_and_are_turned_away_on_load() {
+        let kind = ReactionType::Fusion(chem(1, 0), chem(2, 1), chem(3, 1));
+        let genome = Genome::from_genes(vec![Gene::Reaction(Reaction::new(kind, 0))]);
+        assert_eq!(genome.validate(), vec![ValidationError::ZeroCoefficient(0, 1)]);
+        let mut creature = Creature::new();
+        creature.inject(1, 10);
+        creature.inject(2, 10);
+        genome.step(&mut creature);
+        assert_eq!(creature.chemo_body().peek(3), 0);
+        let path = temp_path("zero-coefficient");
+        genome.save(&path).unwrap();
+        let loaded = Genome::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+    }
+
+    #[test]
+    fn custom_genes_load_on_any_thread() {
+        let saved = encode(&Genome::from_genes(vec![Gene::Custom(custom_square(4))])).unwrap();
+        let genome = thread::spawn(move || decode::<Genome>(&saved)).join().unwrap().unwrap();
+        let mut creature = Creature::new();
+        creature.inject(4, 160);
+        genome.step(&mut creature);
+        assert_eq!(creature.chemo_body().peek(4), 100);
+    }
+
+    #[test]
+    fn leaks_carry_a_pulse_on_for_each_creature_with_its_own_context() {
+        let genome = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 1, 10, 40, 0, 0, false, false)),
+        ]);
+        let leaky = || StepContext { leak: Some(0.5), .. StepContext::new() };
+        let (genomes, contexts) = ((genome.clone(), genome.clone()), (leaky(), leaky()));
+        let (mut first, mut second) = (Creature::new(), Creature::new());
+        let mut tails = (Vec::new(), Vec::new());
+        for _ in 0 .. 15 {
+            genomes.0.step_with(&mut first, &contexts.0);
+            genomes.1.step_with(&mut second, &contexts.1);
+            tails.0.push(first.chemo_body().peek(1));
+            tails.1.push(second.chemo_body().peek(1));
+        }
+        assert_eq!(&tails.0[9 ..], &[40, 60, 70, 75, 77, 78]);
+        assert_eq!(tails.0, tails.1);
+    }
+
+    #[test]
+    fn snapshot_steps_move_a_chain_one_link_per_step() {
+        let catalytic = |from, to| {
+            Gene::Reaction(Reaction::new(ReactionType::Catalytic(chem(1, 1), chem(from, 1),
+                                                                 chem(to, 1)), 0))
+        };
+        let genome = Genome::from_genes(vec![
+            Gene::InitialState(chem(1, 255)),
+            Gene::InitialState(chem(2, 10)),
+            catalytic(2, 3),
+            catalytic(3, 4),
+        ]);
+        let run = |sequential: bool, steps: usize| {
+            let ctx = StepContext { sequential: sequential, .. StepContext::new() };
+            let mut creature = Creature::new();
+            genome.init(&mut creature);
+            genome.clone().step_n_with(&mut creature, &ctx, steps);
+            let body = creature.chemo_body();
+            (body.peek(2), body.peek(3), body.peek(4))
+        };
+        assert_eq!(run(true, 1), (0, 0, 10));
+        assert_eq!(run(false, 1), (0, 10, 0));
+        assert_eq!(run(false, 2), (0, 0, 10));
+    }
+
+    #[test]
+    fn reaction_groups_count_towards_cycles_and_throughput() {
+        let genome = Genome::from_genes(vec![
+            Gene::Reaction(Reaction::new(ReactionType::Decay(chem(9, 1)), 1)),
+            Gene::ReactionGroup(ReactionGroup::new(vec![
+                ReactionType::Split(chem(1, 1), chem(2, 1), chem(5, 1))
+            ], 0)),
+            Gene::Reaction(Reaction::new(ReactionType::Fusion(chem(2, 1), chem(6, 1),
+                                                              chem(1, 1)), 0)),
+        ]);
+        assert_eq!(genome.reaction_cycles(), vec![vec![1, 2]]);
+        let mut creature = Creature::new();
+        creature.inject(1, 10);
+        genome.step(&mut creature);
+        let group = match genome.genes[1] {
+            Gene::ReactionGroup(ref g) => g,
+            _ => unreachable!(),
+        };
+        assert_eq!(group.throughput(), 10.0);
+        genome.reset_throughput();
+        assert_eq!(group.throughput(), 0.0);
+    }
+
+    #[test]
+    fn in_range_takes_its_bounds_either_way_round() {
+        let gene = Gene::Reaction(Reaction::new(ReactionType::Fusion(chem(3, 1), chem(40, 1),
+                                                                     chem(200, 1)), 0));
+        for &ids in &[(10, 20), (20, 10)] {
+            let folded = gene_chemicals(&in_range(&gene, ids));
+            assert!(folded.iter().all(|&id| id >= 10 && id <= 20));
+        }
+        assert_eq!(gene_chemicals(&in_range(&gene, (5, 5))), vec![5, 5, 5]);
+    }
+
+    #[test]
+    fn step_traces_list_chemicals_in_order() {
+        let genome = Genome::from_genes(vec![
+            Gene::Reaction(Reaction::new(ReactionType::Split(chem(9, 1), chem(2, 1), chem(5, 1)),
+                                         0)),
+        ]);
+        let mut creature = Creature::new();
+        creature.inject(9, 10);
+        let trace = genome.step_trace(&mut creature);
+        assert_eq!(encode(&trace).unwrap(), r#"{"deltas":[{"2":10,"5":10,"9":-10}]}"#);
+    }
+
+    #[test]
+    fn emitters_in_two_groups_keep_to_the_tighter_budget() {
+        let genome = Genome::from_genes(vec![
+            Gene::Emitter(Emitter::new(IoType::Digital, 1, 0, 100, 0, 0, false, false)),
+            Gene::Emitter(Emitter::new(IoType::Digital, 2, 0, 100, 0, 0, false, false)),
+        ]);
+        let tight = EmitterGroup::new(vec![0], 50);
+        let loose = EmitterGroup::new(vec![0, 1], 150);
+        for groups in vec![vec![tight.clone(), loose.clone()], vec![loose, tight]] {
+            let ctx = StepContext { emitter_groups: groups, .. StepContext::new() };
+            let mut creature = Creature::new();
+            genome.clone().step_with(&mut creature, &ctx);
+            let body = creature.chemo_body();
+            assert_eq!((body.peek(1), body.peek(2)), (50, 75));
+        }
+    }
 }
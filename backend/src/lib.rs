@@ -1,12 +1,31 @@
+//! Thread safety: everything here is `Send`, so genomes, creatures and step contexts can be moved
+//! to other threads, but genes count ticks in `Cell`s and creatures hold their state in
+//! `RefCell`s, so neither is `Sync`. Evaluate genomes in parallel by cloning the genome for each
+//! thread and giving each thread its own creature. Custom genes must be `Send + Sync` to keep it
+//! that way, and once registered they can be loaded on any thread.
+//!
+//! Mutations and generations are logged at debug level, and reaction and receptor firings at trace
+//! level, through the `log` crate. Nothing is printed unless a logger is installed.
+
+#[macro_use]
+extern crate log;
 extern crate rand;
 extern crate rustc_serialize;
 
 pub mod brain;
 pub mod chem;
+pub mod context;
 pub mod creature;
+pub mod evolution;
 pub mod genome;
+pub mod organism;
+pub mod stimulus;
 
 pub use brain::*;
 pub use chem::*;
+pub use context::*;
 pub use creature::*;
+pub use evolution::*;
 pub use genome::*;
+pub use organism::*;
+pub use stimulus::*;
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use chem::{Concentration, Id};
+use creature::Creature;
+use genome::Genome;
+use stimulus::Stimulus;
+use rustc_serialize::json::{decode, encode};
+
+/// A genome bundled with the creature it drives. The genome carries the emitter and reaction tick
+/// counters, so an organism that is saved and loaded again resumes mid-cycle exactly where it was.
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct Organism {
+    pub genome: Genome,
+    pub creature: Creature,
+}
+
+impl Organism {
+    pub fn new(genome: Genome) -> Organism {
+        let mut creature = Creature::new();
+        genome.init(&mut creature);
+        Organism { genome: genome, creature: creature }
+    }
+
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Organism> {
+        let mut f = try!(File::open(path.as_ref()));
+        let mut data = String::new();
+        try!(f.read_to_string(&mut data));
+        decode(&data).map_err(|_|
+            Error::new(ErrorKind::InvalidInput, "Failed to decode organism.")
+        )
+    }
+
+    pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<()> {
+        let mut f = try!(File::create(path.as_ref()));
+        try!(f.write_all(try!(encode(self).map_err(|_|
+            Error::new(ErrorKind::InvalidInput, "Failed to encode organism.")
+        )).as_bytes()));
+        f.flush()
+    }
+
+    pub fn step(&mut self) {
+        self.genome.step(&mut self.creature)
+    }
+
+    /// Copies the organism so that the copy can be run down a different path. The creature's loci
+    /// and chemistry are copied, as are the genome's tick counters and receptor state, so stepping
+    /// either one leaves the other as it was. Only the behaviour of custom genes is shared, and it
+    /// cannot change.
+    pub fn fork(&self) -> Organism {
+        Organism { genome: self.genome.clone(), creature: self.creature.clone() }
+    }
+
+    /// Steps the organism up to `ticks` times, sending the creature's concentrations through `tx`
+    /// after every step so that another thread can follow along. Stops early once the receiver is
+    /// gone, and gives the number of steps that were taken.
+    pub fn run_streaming(&mut self, ticks: usize, tx: Sender<HashMap<Id, Concentration>>)
+        -> usize {
+        for t in 0 .. ticks {
+            self.step();
+            if tx.send(self.creature.concentrations()).is_err() { return t + 1 }
+        }
+        ticks
+    }
+
+    /// Steps the organism `ticks` times, recording its concentrations at the start and after
+    /// steps spaced `stride` apart, and gives the record along with the stride. The stride starts
+    /// at 1 and doubles whenever the record would grow past `max_history` snapshots (at least 1),
+    /// with every other snapshot dropped to match, so the record stays evenly spaced, reaches from
+    /// the start to within a stride of the end and never holds more than `max_history` snapshots.
+    /// Snapshot `i` was taken after `i * stride` steps.
+    pub fn run_history(&mut self, ticks: usize, max_history: usize)
+        -> (Vec<HashMap<Id, Concentration>>, usize) {
+        let max_history = max_history.max(1);
+        let mut history = vec![self.creature.concentrations()];
+        let mut stride = 1;
+        for t in 1 .. ticks + 1 {
+            self.step();
+            if t % stride != 0 { continue }
+            if history.len() == max_history {
+                history = history.into_iter().enumerate().filter(|&(i, _)| i % 2 == 0)
+                                 .map(|(_, snapshot)| snapshot).collect();
+                stride *= 2;
+                if t % stride != 0 { continue }
+            }
+            history.push(self.creature.concentrations());
+        }
+        (history, stride)
+    }
+
+    /// Steps the organism `ticks` times, applying each of `stimuli` to the creature before every
+    /// step, in order. Steps are counted from 0 for each call.
+    pub fn run_with_stimuli(&mut self, stimuli: &[Stimulus], ticks: usize) {
+        for t in 0 .. ticks {
+            for stimulus in stimuli.iter() {
+                stimulus.apply(&self.creature, t);
+            }
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::sync::mpsc::channel;
+    use chem::{Chemical, Emitter, IoType, Reaction, ReactionType};
+    use genome::Gene;
+    use stimulus::Stimulus;
+    use super::*;
+
+    fn organism() -> Organism {
+        Organism::new(Genome::from_genes(vec![
+            Gene::InitialState(Chemical::with_concentration(1, 100)),
+            Gene::Emitter(Emitter::new(IoType::Digital, 2, 3, 7, 0, 0, false, false)),
+            Gene::Reaction(Reaction::new(ReactionType::Fusion(
+                Chemical::with_concentration(1, 1), Chemical::with_concentration(2, 1),
+                Chemical::with_concentration(3, 1)), 4)),
+        ]))
+    }
+
+    #[test]
+    fn reloaded_organisms_carry_on_where_they_left_off() {
+        let mut original = organism();
+        for _ in 0 .. 5 {
+            original.step();
+        }
+        let path = env::temp_dir().join(format!("life-sim-organism-{}.json", process::id()));
+        original.save(&path).unwrap();
+        let mut loaded = Organism::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.creature.concentrations(), original.creature.concentrations());
+        for _ in 0 .. 12 {
+            original.step();
+            loaded.step();
+            assert_eq!(loaded.creature.concentrations(), original.creature.concentrations());
+        }
+    }
+
+    #[test]
+    fn streaming_sends_a_snapshot_after_every_step() {
+        let (mut streamed, mut stepped) = (organism(), organism());
+        let (tx, rx) = channel();
+        assert_eq!(streamed.run_streaming(10, tx), 10);
+        let snapshots: Vec<_> = rx.iter().collect();
+        assert_eq!(snapshots.len(), 10);
+        for snapshot in snapshots {
+            stepped.step();
+            assert_eq!(snapshot, stepped.creature.concentrations());
+        }
+    }
+
+    #[test]
+    fn streaming_stops_once_the_receiver_is_gone() {
+        let mut organism = organism();
+        let (tx, rx) = channel();
+        drop(rx);
+        assert_eq!(organism.run_streaming(10, tx), 1);
+    }
+
+    #[test]
+    fn history_stays_under_its_cap_and_spans_the_whole_run() {
+        let (mut recorded, mut stepped) = (organism(), organism());
+        let (history, stride) = recorded.run_history(100, 8);
+        assert_eq!(stride, 16);
+        assert_eq!(history.len(), 7);
+        assert_eq!(history[0], stepped.creature.concentrations());
+        for snapshot in history[1 ..].iter() {
+            for _ in 0 .. stride {
+                stepped.step();
+            }
+            assert_eq!(*snapshot, stepped.creature.concentrations());
+        }
+        let (history, stride) = organism().run_history(5, 8);
+        assert_eq!((history.len(), stride), (6, 1));
+        let (history, stride) = organism().run_history(3, 0);
+        assert_eq!((history.len(), stride), (1, 4));
+    }
+
+    #[test]
+    fn pulses_raise_a_chemical_and_let_it_fall_again() {
+        let pulse = [Stimulus::Pulse { chemical: 5, start: 3, length: 4, level: 80 }];
+        let levels: Vec<_> = (1 .. 11).map(|ticks| {
+            let mut organism = organism();
+            organism.run_with_stimuli(&pulse, ticks);
+            let level = organism.creature.chemo_body().peek(5);
+            level
+        }).collect();
+        assert_eq!(levels, vec![0, 0, 0, 80, 80, 80, 80, 0, 0, 0]);
+    }
+
+    #[test]
+    fn stepping_a_fork_leaves_the_original_alone() {
+        let mut original = organism();
+        original.step();
+        original.step();
+        let before = original.creature.concentrations();
+        let mut fork = original.fork();
+        fork.creature.set_locus(9, 4);
+        for _ in 0 .. 10 {
+            fork.step();
+        }
+        assert_eq!(original.creature.concentrations(), before);
+        assert_eq!(original.creature.get_locus(9), 0);
+        assert!(fork.creature.concentrations() != before);
+        let mut control = organism();
+        for _ in 0 .. 12 {
+            control.step();
+        }
+        for _ in 0 .. 10 {
+            original.step();
+        }
+        assert_eq!(original.creature.concentrations(), control.creature.concentrations());
+    }
+}
@@ -0,0 +1,69 @@
+use chem::{Concentration, Id};
+use creature::Creature;
+
+/// A scripted course for one chemical's concentration, for testing how creatures respond to their
+/// surroundings the same way every time. While a stimulus is active it sets its chemical to the
+/// level it scripts for each step just before the step runs, whatever the genome did before; the
+/// genome is then free to move the chemical during the step. The rest of the time it leaves the
+/// chemical alone. Steps are counted from 0.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub enum Stimulus {
+    /// Holds the chemical at `level` from step `start` on.
+    Step { chemical: Id, start: usize, level: Concentration },
+    /// Holds the chemical at `level` for `length` steps from `start`, then empties it once on the
+    /// step the pulse ends.
+    Pulse { chemical: Id, start: usize, length: usize, level: Concentration },
+    /// Moves the chemical in a straight line from `from` at step `start` to `to` at step
+    /// `start + length`, holding it at each level along the way.
+    Ramp { chemical: Id, start: usize, length: usize, from: Concentration, to: Concentration },
+}
+
+impl Stimulus {
+    pub fn chemical(&self) -> Id {
+        match *self {
+            Stimulus::Step { chemical, .. } |
+            Stimulus::Pulse { chemical, .. } |
+            Stimulus::Ramp { chemical, .. } => chemical,
+        }
+    }
+
+    /// The level the stimulus holds its chemical at on step `tick`, if it is active then.
+    pub fn level(&self, tick: usize) -> Option<Concentration> {
+        match *self {
+            Stimulus::Step { start, level, .. } if tick >= start => Some(level),
+            Stimulus::Pulse { start, length, level, .. } if tick >= start => {
+                if tick < start + length {
+                    Some(level)
+                } else if tick == start + length {
+                    Some(0)
+                } else {
+                    None
+                }
+            },
+            Stimulus::Ramp { start, length, from, to, .. } if tick >= start => {
+                if tick > start + length { return None }
+                let progress = if length == 0 {
+                    1.0
+                } else {
+                    (tick - start) as f32 / length as f32
+                };
+                Some((from as f32 + (to as f32 - from as f32) * progress).round() as u8)
+            },
+            _ => None,
+        }
+    }
+
+    /// Sets the creature's concentration of the chemical to the level for step `tick`, if the
+    /// stimulus is active then.
+    pub fn apply(&self, creature: &Creature, tick: usize) {
+        if let Some(level) = self.level(tick) {
+            let id = self.chemical();
+            let current = creature.chemo_body().peek(id);
+            if level > current {
+                creature.inject(id, level - current);
+            } else {
+                creature.drain(id, current - level);
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+extern crate backend;
+
+use std::{env, fs, process};
+use std::path::PathBuf;
+use std::process::Command;
+use backend::*;
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("life-sim-evolve-{}-{}.json", name, process::id()))
+}
+
+/// How many ticks a creature driven by `genome` takes to be a baby, as `evolve` scores it.
+fn ticks_to_baby(genome: &Genome, cap: u32) -> u32 {
+    let mut creature = Creature::new();
+    genome.init(&mut creature);
+    (0 .. cap).find(|_| {
+        genome.step(&mut creature);
+        creature.age() == Age::Baby
+    }).unwrap_or(cap)
+}
+
+#[test]
+fn evolving_writes_the_genome_to_the_output_path() {
+    let (output, checkpoint) = (temp_path("output"), temp_path("unused-checkpoint"));
+    let status = Command::new(env!("CARGO_BIN_EXE_evolve"))
+        .args(&["--ticks", "0", "--seed", "1", "--output"]).arg(&output)
+        .arg("--checkpoint").arg(&checkpoint)
+        .status().unwrap();
+    assert!(status.success());
+    Genome::load(&output).unwrap();
+    fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn resuming_carries_on_from_the_checkpoint() {
+    let (output, checkpoint) = (temp_path("resumed"), temp_path("checkpoint"));
+    // Setting every ageing locus makes a baby on the first tick, which no fresh run of 20 ticks
+    // is likely to stumble on.
+    let fit = Genome::from_genes((1 .. 7).map(|locus| {
+        Gene::Receptor(Receptor::new(IoType::Digital, 1, locus, 1, 0, 0, false))
+    }).collect());
+    assert_eq!(ticks_to_baby(&fit, 20), 0);
+    fit.save(&checkpoint).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_evolve"))
+        .args(&["--ticks", "20", "--seed", "1", "--resume", "--output"]).arg(&output)
+        .arg("--checkpoint").arg(&checkpoint)
+        .status().unwrap();
+    assert!(status.success());
+    assert_eq!(ticks_to_baby(&Genome::load(&output).unwrap(), 20), 0);
+    fs::remove_file(&output).unwrap();
+    fs::remove_file(&checkpoint).unwrap();
+}